@@ -4,8 +4,12 @@ pub struct Button {
     elem: Elem,
 }
 
+pub struct ButtonProps<'a> {
+    pub text: &'a str,
+}
+
 impl Button {
-    pub fn new(text: &str) -> Self {
+    pub fn new(ButtonProps { text }: ButtonProps) -> Self {
         let text = text.to_owned();
         Self {
             elem: Elem::new("button")
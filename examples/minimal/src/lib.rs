@@ -1,29 +1,33 @@
-use button::Button;
+use button::{Button, ButtonProps};
 use web::{
-    components::Component,
-    console_log,
+    commands, console_log,
     element::Elem,
-    js::{self, JsValue},
+    events::{FromEvent, InputEvent},
+    html,
+    js::JsValue,
+    js_fn,
+    router::{Mode, Router},
+    signals::Signal,
 };
 
 mod button;
 
 #[no_mangle]
 pub fn main() {
+    // Send DOM operations to JS in batches
+    commands::set_batching(true);
+
     // Test all data types
-    js::invoke(
-        "console.log('[RUST]', {}, {}, {}, {}, {}, {}, {}, {})",
-        &[
-            JsValue::Undefined,
-            JsValue::Null,
-            true.into(),
-            false.into(),
-            1.23.into(),
-            JsValue::BigInt(1234),
-            "Hello!".into(),
-            vec![0x12, 0x34, 0x56].into(),
-        ],
-    );
+    js_fn!("console.log('[RUST]', {}, {}, {}, {}, {}, {}, {}, {})").invoke(&[
+        JsValue::Undefined,
+        JsValue::Null,
+        true.into(),
+        false.into(),
+        1.23.into(),
+        JsValue::BigInt(1234),
+        "Hello!".into(),
+        vec![0x12, 0x34, 0x56].into(),
+    ]);
 
     // Create some layout
     let body = Elem::select("body").unwrap();
@@ -32,26 +36,34 @@ pub fn main() {
 
     let body = body.class("bg-gray-800");
 
-    let body = body.append(
-        &Elem::new("div")
-            .class("flex flex-row gap-40 w-screen justify-around p-4")
-            .children(&[
-                &Button::new("Click me!").to_elem(),
-                Button::new("No, click me!").to_elem(),
-                Button::new("Or me!").to_elem(),
-            ]),
-    );
-
-    let text = Elem::new("span").class("text-red-800");
-
-    let body = body.append(&text);
-
-    let input = Elem::new("input").class("m-8").on("input", move |event| {
-        let value = js::invoke("return {}.target.value", &[event.into()])
-            .to_string()
-            .unwrap();
-        text.clone().children(&[&Elem::new("span").text(&value)]);
-    });
+    let value = Signal::new(String::new());
 
-    body.append(&input);
+    // Follow links in the hash of the URL, which also works for pages opened from a file
+    let pages = Router::new()
+        .mode(Mode::Hash)
+        .route("/", |_| Elem::new("p").text("home page"))
+        .route("/about", |_| Elem::new("p").text("about page"))
+        .outlet();
+    let link = Elem::new("a")
+        .attr("id", "about-link")
+        .attr("href", "/about")
+        .text("About");
+    let body = body.append(&link).append(&pages);
+
+    body.append(&html! {
+        <div>
+            <div class="flex flex-row gap-40 w-screen justify-around p-4">
+                <Button text="Click me!" />
+                <Button text="No, click me!" />
+                <Button text="Or me!" />
+            </div>
+            <span class="text-red-800">{value}</span>
+            <input
+                class="m-8"
+                on:input={move |event| {
+                    value.set(InputEvent::from_event(event).target_value().unwrap_or_default());
+                }}
+            />
+        </div>
+    });
 }
@@ -8,6 +8,28 @@ const decoder = new TextDecoder()
 const objects = []
 const free = []
 
+// Compiled snippets indexed by snippet id. With the `csp` feature, the snippets
+// are generated ahead of time by `snippets.js` and loaded before this script
+const snippets = new Map(globalThis.webSnippets ?? [])
+// Compiled functions of `__invoke`, indexed by their code. The code of `__invoke` can be built
+// at runtime, so only the most recently used functions are kept, in the order of their last use
+const compiled = new Map()
+const COMPILED_LIMIT = 256
+
+// Typed arrays indexed by their kind in the wire format. `Uint8Array` is serialized as a blob
+const typedArrays = [
+    Int8Array, Int16Array, Uint16Array, Int32Array, Uint32Array,
+    Float32Array, Float64Array, BigInt64Array, BigUint64Array,
+]
+
+// Functions of Rust closures indexed by closure id, so that a closure is always the same function
+const closures = new Map()
+// Result of the closure that has just been called, see `__closure_return`
+let closureResult
+
+// Event listeners indexed by callback id, so that they can be removed later on
+const listeners = new Map()
+
 function storeObject(object) {
     const id = free.pop()
     if (id !== undefined) {
@@ -39,96 +61,272 @@ function serializeU32(i) {
     return new Uint8Array(buffer)
 }
 
-function serialize(values) {
+// Arrays and plain objects are copied up to the given depth, and referenced below it.
+// Copying an array or object that contains itself throws a `TypeError`.
+function serialize(values, depth = 0) {
     const buffer = []
 
     const length = values.length
     buffer.push(...new Uint8Array(new Uint32Array([length]).buffer))
 
     for (const value of values) {
-        if (value === undefined) {
-            buffer.push(0x00)
-        } else if (value === null) {
-            buffer.push(0x01)
-        } else if (typeof value === 'boolean') {
-            buffer.push(value ? 0x02 : 0x03)
-        } else if (typeof value === 'number') {
-            buffer.push(0x04)
-            buffer.push(...serializeF64(value))
-        } else if (typeof value === 'bigint') {
-            buffer.push(0x05)
-            buffer.push(...serializeBigInt(value))
-        } else if (typeof value === 'string') {
-            buffer.push(0x06)
-            const encoded = encoder.encode(value)
-            buffer.push(...serializeU32(encoded.length))
-            buffer.push(...encoded)
-        } else if (value instanceof Uint8Array) {
-            buffer.push(0x09)
-            buffer.push(...serializeU32(value.length))
-            buffer.push(...value)
-        } else if (typeof value === 'object') {
-            buffer.push(Array.isArray(value) ? 0x07 : 0x08)
-            buffer.push(...serializeU32(storeObject(value)))
-        } else {
-            throw new Error(`could not serialize object of type ${typeof value}`)
+        serializeValue(buffer, value, depth)
+    }
+
+    return new Uint8Array(buffer)
+}
+
+function serializeString(buffer, value) {
+    const encoded = encoder.encode(value)
+    buffer.push(...serializeU32(encoded.length))
+    buffer.push(...encoded)
+}
+
+// Plain objects are copied when serializing deeply, other objects are always referenced
+function isPlainObject(value) {
+    const prototype = Object.getPrototypeOf(value)
+    return prototype === Object.prototype || prototype === null
+}
+
+// `ancestors` are the arrays and plain objects that are being copied
+function serializeValue(buffer, value, depth, ancestors = new Set()) {
+    if (value === undefined) {
+        buffer.push(0x00)
+    } else if (value === null) {
+        buffer.push(0x01)
+    } else if (typeof value === 'boolean') {
+        buffer.push(value ? 0x02 : 0x03)
+    } else if (typeof value === 'number') {
+        buffer.push(0x04)
+        buffer.push(...serializeF64(value))
+    } else if (typeof value === 'bigint') {
+        buffer.push(0x05)
+        buffer.push(...serializeBigInt(value))
+    } else if (typeof value === 'string') {
+        buffer.push(0x06)
+        serializeString(buffer, value)
+    } else if (value instanceof Uint8Array) {
+        buffer.push(0x09)
+        buffer.push(...serializeU32(value.length))
+        buffer.push(...value)
+    } else if (typedArrays.some((type) => value instanceof type)) {
+        const bytes = new Uint8Array(value.buffer, value.byteOffset, value.byteLength)
+        buffer.push(0x0D)
+        buffer.push(typedArrays.findIndex((type) => value instanceof type))
+        buffer.push(...serializeU32(bytes.length))
+        buffer.push(...bytes)
+    } else if (depth > 0 && Array.isArray(value)) {
+        enterCopy(ancestors, value)
+        buffer.push(0x0B)
+        buffer.push(...serializeU32(value.length))
+        for (const item of value) {
+            serializeValue(buffer, item, depth - 1, ancestors)
+        }
+        ancestors.delete(value)
+    } else if (depth > 0 && typeof value === 'object' && isPlainObject(value)) {
+        enterCopy(ancestors, value)
+        const entries = Object.entries(value)
+        buffer.push(0x0C)
+        buffer.push(...serializeU32(entries.length))
+        for (const [key, item] of entries) {
+            serializeString(buffer, key)
+            serializeValue(buffer, item, depth - 1, ancestors)
         }
+        ancestors.delete(value)
+    } else if (typeof value === 'object' || typeof value === 'function') {
+        buffer.push(Array.isArray(value) ? 0x07 : 0x08)
+        buffer.push(...serializeU32(storeObject(value)))
+    } else {
+        throw new Error(`could not serialize object of type ${typeof value}`)
     }
+}
+
+function enterCopy(ancestors, value) {
+    if (ancestors.has(value)) {
+        throw new TypeError('cannot copy a cyclic object')
+    }
+    ancestors.add(value)
+}
 
+// Errors are serialized as a single value with tag 0x0A, followed by the name, message and stack
+function serializeError(error) {
+    const buffer = [...serializeU32(1), 0x0A]
+    for (const value of [error?.name ?? 'Error', error?.message ?? String(error), error?.stack ?? '']) {
+        serializeString(buffer, String(value))
+    }
     return new Uint8Array(buffer)
 }
 
+// Writes the result of `f` to memory, or the error if `f` throws
+function respond(f, depth = 0) {
+    let buffer
+    try {
+        buffer = serialize([f()], depth)
+    } catch (error) {
+        buffer = serializeError(error)
+    }
+    writeBufferToMemory(buffer)
+}
+
+// Returns the JS function that calls the Rust closure with the given id
+function getClosure(id) {
+    let closure = closures.get(id)
+    if (closure === undefined) {
+        closure = (...args) => {
+            // The closure does nothing once it has been freed by Rust
+            if (closures.get(id) !== closure) return undefined
+            writeBufferToMemory(serialize(args))
+            wasmModule.instance.exports.call_closure(id)
+            const result = closureResult
+            closureResult = undefined
+            return result
+        }
+        closures.set(id, closure)
+    }
+    return closure
+}
+
+function compile(code) {
+    return Function(`'use strict';return(${code})`)()
+}
+
 function deserialize(buffer) {
-    const view = new DataView(buffer.buffer)
+    const view = new DataView(buffer.buffer, buffer.byteOffset, buffer.byteLength)
     const values = []
-    let i = 4 // first 4 bytes encode number of values
-    while (i < buffer.length) {
-        let x = buffer[i]
-        if (x == 0x00) {
-            values.push(undefined)
-            i += 1
-        } else if (x == 0x01) {
-            values.push(null)
-            i += 1
-        } else if (x == 0x02) {
-            values.push(true)
-            i += 1
-        } else if (x == 0x03) {
-            values.push(false)
-            i += 1
-        } else if (x == 0x04) {
-            values.push(view.getFloat64(i + 1, true))
-            i += 1 + 8
-        } else if (x == 0x05) {
-            values.push(view.getBigInt64(i + 1, true))
-            i += 1 + 8
-        } else if (x == 0x06) {
-            const len = view.getUint32(i + 1, true)
-            values.push(decoder.decode(buffer.subarray(i + 1 + 4, i + 1 + 4 + len)))
-            i += 1 + 4 + len
-        } else if (x == 0x07 || x == 0x08) {
-            const id = view.getUint32(i + 1, true)
-            values.push(objects[id])
-            i += 1 + 4
-        } else if (x == 0x09) {
-            const len = view.getUint32(i + 1, true)
-            values.push(buffer.subarray(i + 1 + 4, i + 1 + 4 + len))
-            i += 1 + 4 + len
-        } else {
-            throw new Error(`invalid parameter type (0x${x.toString(16)})`)
-        }
+    const position = { i: 4 } // first 4 bytes encode number of values
+    while (position.i < buffer.length) {
+        values.push(deserializeValue(buffer, view, position))
     }
     return values
 }
 
+function deserializeString(buffer, view, position) {
+    const len = view.getUint32(position.i, true)
+    const value = decoder.decode(buffer.subarray(position.i + 4, position.i + 4 + len))
+    position.i += 4 + len
+    return value
+}
+
+// Reads the value at `position.i`, and moves `position.i` to the end of the value
+function deserializeValue(buffer, view, position) {
+    const x = buffer[position.i]
+    const i = position.i + 1
+    position.i = i
+    if (x == 0x00) {
+        return undefined
+    } else if (x == 0x01) {
+        return null
+    } else if (x == 0x02) {
+        return true
+    } else if (x == 0x03) {
+        return false
+    } else if (x == 0x04) {
+        position.i += 8
+        return view.getFloat64(i, true)
+    } else if (x == 0x05) {
+        position.i += 8
+        return view.getBigInt64(i, true)
+    } else if (x == 0x06) {
+        return deserializeString(buffer, view, position)
+    } else if (x == 0x07 || x == 0x08) {
+        position.i += 4
+        return objects[view.getUint32(i, true)]
+    } else if (x == 0x09) {
+        const len = view.getUint32(i, true)
+        position.i += 4 + len
+        return buffer.subarray(i + 4, i + 4 + len)
+    } else if (x == 0x0B) {
+        const len = view.getUint32(i, true)
+        position.i += 4
+        return Array.from({ length: len }, () => deserializeValue(buffer, view, position))
+    } else if (x == 0x0C) {
+        const len = view.getUint32(i, true)
+        position.i += 4
+        const object = {}
+        for (let n = 0; n < len; n++) {
+            const key = deserializeString(buffer, view, position)
+            object[key] = deserializeValue(buffer, view, position)
+        }
+        return object
+    } else if (x == 0x0D) {
+        const type = typedArrays[buffer[i]]
+        const len = view.getUint32(i + 1, true)
+        position.i += 1 + 4 + len
+        // Copy the bytes, so that the elements are aligned
+        return new type(buffer.slice(i + 5, i + 5 + len).buffer)
+    } else if (x == 0x0E) {
+        position.i += 4
+        return getClosure(view.getUint32(i, true))
+    } else {
+        throw new Error(`invalid parameter type (0x${x.toString(16)})`)
+    }
+}
+
 function getWasmImports() {
     const env = {
         __invoke(c_ptr, c_len, p_ptr, p_len) {
-            const funcBody = decoder.decode(readBufferFromMemory(c_ptr, c_len));
-            const func = Function(`'use strict';return(${funcBody})`)()
-            const values = deserialize(readBufferFromMemory(p_ptr, p_len))
-            const result = func.call({}, ...values)
-            writeBufferToMemory(serialize([result]))
+            respond(() => {
+                const code = decoder.decode(readBufferFromMemory(c_ptr, c_len));
+                let func = compiled.get(code)
+                if (func === undefined) {
+                    func = compile(code)
+                    if (compiled.size >= COMPILED_LIMIT) {
+                        compiled.delete(compiled.keys().next().value)
+                    }
+                } else {
+                    compiled.delete(code)
+                }
+                compiled.set(code, func)
+                const values = deserialize(readBufferFromMemory(p_ptr, p_len))
+                return func.call({}, ...values)
+            })
+        },
+        __register_snippet(c_ptr, c_len) {
+            const code = decoder.decode(readBufferFromMemory(c_ptr, c_len));
+            const id = snippets.size + 1 // id 0 means "not registered" on the Rust side
+            snippets.set(id, compile(code))
+            return id
+        },
+        __invoke_snippet(id, p_ptr, p_len) {
+            respond(() => {
+                const values = deserialize(readBufferFromMemory(p_ptr, p_len))
+                return snippets.get(id).call({}, ...values)
+            })
+        },
+        __flush_commands(c_ptr, c_len, f_ptr, f_len) {
+            respond(() => {
+                // Each call is the snippet id, the length of the params and the params
+                const buffer = readBufferFromMemory(c_ptr, c_len)
+                const view = new DataView(buffer.buffer)
+                const count = view.getUint32(0, true)
+                let i = 4
+                try {
+                    for (let n = 0; n < count; n++) {
+                        const id = view.getUint32(i, true)
+                        const len = view.getUint32(i + 4, true)
+                        const values = deserialize(buffer.subarray(i + 8, i + 8 + len))
+                        snippets.get(id).call({}, ...values)
+                        i += 8 + len
+                    }
+                } finally {
+                    // Objects are freed after the calls that use them, also if a call throws
+                    const freed = new DataView(readBufferFromMemory(f_ptr, f_len * 4).buffer)
+                    for (let n = 0; n < f_len; n++) {
+                        const id = freed.getUint32(n * 4, true)
+                        objects[id] = undefined
+                        free.push(id)
+                    }
+                }
+            })
+        },
+        __request_flush() {
+            requestAnimationFrame(() => wasmModule.instance.exports.flush_commands())
+        },
+        __closure_return(r_ptr, r_len) {
+            closureResult = deserialize(readBufferFromMemory(r_ptr, r_len))[0]
+        },
+        __free_closure(closure_id) {
+            closures.delete(closure_id)
         },
         __free_object(id) {
             objects[id] = undefined
@@ -136,18 +334,82 @@ function getWasmImports() {
         },
         __query_selector(q_ptr, q_len) {
             const query = decoder.decode(readBufferFromMemory(q_ptr, q_len));
-            writeBufferToMemory(serialize([document.querySelector(query)]));
+            respond(() => document.querySelector(query));
+        },
+        __read_deep(object_id) {
+            respond(() => objects[object_id], Infinity)
+        },
+        __read_array(object_id) {
+            respond(() => objects[object_id], 1)
+        },
+        __get(object_id, k_ptr, k_len) {
+            const key = decoder.decode(readBufferFromMemory(k_ptr, k_len))
+            respond(() => objects[object_id][key])
+        },
+        __get_path(object_id, p_ptr, p_len) {
+            const path = decoder.decode(readBufferFromMemory(p_ptr, p_len))
+            respond(() => path.split('.').reduce((object, key) => object?.[key], objects[object_id]))
+        },
+        __set(object_id, k_ptr, k_len, v_ptr, v_len) {
+            const key = decoder.decode(readBufferFromMemory(k_ptr, k_len))
+            const [value] = deserialize(readBufferFromMemory(v_ptr, v_len))
+            respond(() => { objects[object_id][key] = value })
+        },
+        __call(object_id, m_ptr, m_len, a_ptr, a_len) {
+            const method = decoder.decode(readBufferFromMemory(m_ptr, m_len))
+            const args = deserialize(readBufferFromMemory(a_ptr, a_len))
+            respond(() => objects[object_id][method](...args))
+        },
+        __has(object_id, k_ptr, k_len) {
+            const key = decoder.decode(readBufferFromMemory(k_ptr, k_len))
+            respond(() => key in Object(objects[object_id]))
+        },
+        __keys(object_id) {
+            respond(() => Object.keys(objects[object_id]), 1)
+        },
+        __instance_of(object_id, c_ptr, c_len) {
+            const name = decoder.decode(readBufferFromMemory(c_ptr, c_len))
+            respond(() => {
+                const type = globalThis[name]
+                if (typeof type !== 'function') throw new TypeError(`${name} is not a class`)
+                return objects[object_id] instanceof type
+            })
         },
         __create_element(t_ptr, t_len) {
             const tag = decoder.decode(readBufferFromMemory(t_ptr, t_len));
-            writeBufferToMemory(serialize([document.createElement(tag)]));
+            respond(() => document.createElement(tag));
         },
-        __add_event_listener(object_id, e_ptr, e_len, callback_id) {
+        __add_event_listener(object_id, e_ptr, e_len, callback_id, flags, signal_id) {
             const object = objects[object_id];
             const event = decoder.decode(readBufferFromMemory(e_ptr, e_len));
-            object.addEventListener(event, (event) => {
+            const options = {
+                once: (flags & 0x1) != 0,
+                passive: (flags & 0x2) != 0,
+                capture: (flags & 0x4) != 0,
+                signal: (flags & 0x8) != 0 ? objects[signal_id] : undefined,
+            };
+            const listener = (event) => {
+                if (options.once) listeners.delete(callback_id);
                 wasmModule.instance.exports.call_callback(callback_id, storeObject(event));
-            });
+            };
+            object.addEventListener(event, listener, options);
+            listeners.set(callback_id, { object, event, listener, capture: options.capture });
+        },
+        __remove_event_listener(callback_id) {
+            const entry = listeners.get(callback_id);
+            if (entry === undefined) return;
+            entry.object.removeEventListener(entry.event, entry.listener, { capture: entry.capture });
+            listeners.delete(callback_id);
+        },
+        __schedule_tasks() {
+            queueMicrotask(() => wasmModule.instance.exports.run_tasks())
+        },
+        __await_promise(object_id, future_id) {
+            const settle = (success) => (value) => {
+                writeBufferToMemory(serialize([value]))
+                wasmModule.instance.exports.resolve_future(future_id, success)
+            }
+            Promise.resolve(objects[object_id]).then(settle(true), settle(false))
         }
     }
     return { env }
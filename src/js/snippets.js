@@ -0,0 +1,54 @@
+'use strict'
+
+// Generates the JS snippets of a wasm module built with the `csp` feature, so that
+// no code has to be evaluated at runtime. The output is loaded before `main.js`.
+//
+// node src/js/snippets.js <input.wasm> <output.js>
+
+const fs = require('node:fs')
+
+const decoder = new TextDecoder()
+
+// Each entry is the id and length of the code as u32, followed by the code itself.
+// The same code can be stored several times, but two different codes with the same id
+// (a hash collision) cannot be told apart at runtime, so they fail the generation.
+function readSnippets(wasmBuffer) {
+    const module = new WebAssembly.Module(wasmBuffer)
+    const snippets = new Map()
+    for (const section of WebAssembly.Module.customSections(module, 'web_snippets')) {
+        const view = new DataView(section)
+        let i = 0
+        while (i < section.byteLength) {
+            const id = view.getUint32(i, true)
+            const len = view.getUint32(i + 4, true)
+            const code = decoder.decode(new Uint8Array(section, i + 8, len))
+            const existing = snippets.get(id)
+            if (existing !== undefined && existing !== code) {
+                throw new Error(`snippets ${JSON.stringify(existing)} and ${JSON.stringify(code)} have the same id ${id}`)
+            }
+            snippets.set(id, code)
+            i += 8 + len
+        }
+    }
+    return snippets
+}
+
+// Same as `__code` in `js.rs`, every `{}` becomes a parameter, and an empty object is written as `{ }`
+function toFunction(code) {
+    let count = 0
+    const body = code.replace(/\{\}/g, () => `p${count++}`)
+    const params = Array.from({ length: count }, (_, i) => `p${i}`)
+    return `function(${params.join(',')}){${body}}`
+}
+
+function generate(snippets) {
+    const entries = [...snippets].map(([id, code]) => `    [${id}, ${toFunction(code)}],\n`)
+    return `'use strict'\n\n// Generated by snippets.js\nglobalThis.webSnippets = [\n${entries.join('')}]\n`
+}
+
+if (require.main === module) {
+    const [input, output] = process.argv.slice(2)
+    fs.writeFileSync(output, generate(readSnippets(fs.readFileSync(input))))
+}
+
+module.exports = { readSnippets, toFunction, generate }
@@ -0,0 +1,42 @@
+const test = require('node:test')
+const assert = require('node:assert')
+
+const { readSnippets, toFunction } = require('./snippets')
+
+// node src/js/snippets.test.js
+
+const encoder = new TextEncoder()
+
+// A wasm module with a `web_snippets` custom section of the given entries
+function moduleWithSnippets(entries) {
+    const payload = []
+    for (const [id, code] of entries) {
+        const bytes = encoder.encode(code)
+        const header = new DataView(new ArrayBuffer(8))
+        header.setUint32(0, id, true)
+        header.setUint32(4, bytes.length, true)
+        payload.push(...new Uint8Array(header.buffer), ...bytes)
+    }
+    const name = encoder.encode('web_snippets')
+    const section = [name.length, ...name, ...payload]
+    // Section sizes below 128 fit in a single LEB128 byte
+    assert.ok(section.length < 128)
+    return new Uint8Array([0, 97, 115, 109, 1, 0, 0, 0, 0, section.length, ...section])
+}
+
+test('read snippets', () => {
+    const snippets = readSnippets(moduleWithSnippets([[1, '{}.remove()'], [2, 'return {}'], [1, '{}.remove()']]))
+    assert.deepStrictEqual([...snippets], [[1, '{}.remove()'], [2, 'return {}']])
+})
+
+test('reject snippets with the same id', () => {
+    assert.throws(
+        () => readSnippets(moduleWithSnippets([[1, '{}.remove()'], [1, 'return {}']])),
+        /have the same id 1/,
+    )
+})
+
+test('every placeholder is a parameter', () => {
+    assert.strictEqual(toFunction('{}.setAttribute({},{})'), 'function(p0,p1,p2){p0.setAttribute(p1,p2)}')
+    assert.strictEqual(toFunction('const o = { }; o.x = {}; return o'), 'function(p0){const o = { }; o.x = p0; return o}')
+})
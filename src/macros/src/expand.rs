@@ -0,0 +1,98 @@
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::{format_ident, quote, quote_spanned};
+
+use crate::parse::{Attribute, Component, Element, Node, Value};
+
+// An element or a component expands to an `Elem`, so the root can be either.
+pub fn expand_node(node: &Node) -> TokenStream {
+    match node {
+        Node::Element(element) => expand_element(element),
+        Node::Component(component) => {
+            let component = expand_component(component);
+            quote! { ::web::components::scoped(|| #component) }
+        }
+        Node::Text(text) => quote! { #text },
+        Node::Expr(expr) => quote! { (#expr) },
+    }
+}
+
+fn expand_element(element: &Element) -> TokenStream {
+    let tag = &element.tag.value;
+    let attributes = element.attributes.iter().map(expand_attribute);
+    let children = element.children.iter().map(|child| {
+        let child = expand_node(child);
+        quote! {
+            let __elem = ::web::components::Child::append_to(#child, __elem);
+        }
+    });
+    quote! {
+        {
+            let __elem = ::web::element::Elem::new(#tag);
+            #( let __elem = #attributes; )*
+            #( #children )*
+            __elem
+        }
+    }
+}
+
+fn expand_attribute(attribute: &Attribute) -> TokenStream {
+    let name = &attribute.name.value;
+    let span = attribute.name.span;
+    let to_string = |value: &Value| match value {
+        Value::None => quote! { "" },
+        Value::Lit(lit) => quote! { #lit },
+        Value::Expr(expr) => quote! { &::std::string::ToString::to_string(&(#expr)) },
+    };
+
+    let Some(prefix) = &attribute.prefix else {
+        let value = to_string(&attribute.value);
+        return if name == "class" {
+            quote_spanned! { span=> __elem.class(#value) }
+        } else {
+            quote_spanned! { span=> __elem.attr(#name, #value) }
+        };
+    };
+
+    // Prefixed attributes always have an expression, see `validate`
+    let Value::Expr(expr) = &attribute.value else {
+        unreachable!();
+    };
+    match prefix.value.as_str() {
+        "on" => quote_spanned! { span=> __elem.on(#name, #expr) },
+        "class" => quote_spanned! { span=> __elem.class_toggle(#name, #expr) },
+        "style" => quote_spanned! { span=> __elem.style_dyn(#name, #expr) },
+        "prop" => quote_spanned! { span=> __elem.prop_dyn(#name, #expr) },
+        _ => quote_spanned! { span=> __elem.attr_dyn(#name, #expr) },
+    }
+}
+
+// Components are constructed with `new`, which takes a props struct named after the component,
+// for example `<Badge label="new" />` expands to `Badge::new(BadgeProps { label: "new" })`.
+// Dashes in attribute names are replaced by underscores. The construction runs in the
+// component's own scope, see `components::scoped`.
+fn expand_component(component: &Component) -> TokenStream {
+    let path = &component.path;
+    let mut props = path.clone();
+    if let Some(last) = props.segments.last_mut() {
+        last.ident = format_ident!("{}Props", last.ident, span = last.ident.span());
+    }
+    let fields = component.attributes.iter().map(|attribute| {
+        let name = field_name(&attribute.name.value, attribute.name.span);
+        let value = match &attribute.value {
+            Value::None => quote! { true },
+            Value::Lit(lit) => quote! { #lit },
+            Value::Expr(expr) => quote! { #expr },
+        };
+        quote! { #name: #value }
+    });
+    quote! { #path::new(#props { #( #fields ),* }) }
+}
+
+fn field_name(name: &str, span: Span) -> Ident {
+    let name = name.replace('-', "_");
+    match syn::parse_str::<Ident>(&name) {
+        Ok(_) => Ident::new(&name, span),
+        // Keywords, like `type`
+        Err(_) => Ident::new_raw(&name, span),
+    }
+}
@@ -0,0 +1,34 @@
+use proc_macro::TokenStream;
+use syn::parse_macro_input;
+
+mod expand;
+mod parse;
+mod validate;
+
+// Builds an `Elem` from JSX-like markup, for example
+//
+//     html! {
+//         <div class="counter">
+//             <span>"Count: " {count}</span>
+//             <button on:click={move |_| count.update(|c| *c += 1)}>"+"</button>
+//         </div>
+//     }
+//
+// Attributes are set with `name="value"` or `name={expr}`, and the prefixes `on:`, `class:`,
+// `style:`, `prop:` and `attr:` add event listeners and reactive bindings. Children are string
+// literals, elements, components and `{expr}`, which are inserted with `components::Child`.
+// Components are constructed from their attributes, see `components::Component`.
+#[proc_macro]
+pub fn html(input: TokenStream) -> TokenStream {
+    let node = parse_macro_input!(input as parse::Node);
+    match validate::validate(&node) {
+        Ok(()) => expand::expand_node(&node).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+// Same as `html!`.
+#[proc_macro]
+pub fn view(input: TokenStream) -> TokenStream {
+    html(input)
+}
@@ -0,0 +1,205 @@
+use proc_macro2::Span;
+use syn::{
+    braced,
+    ext::IdentExt,
+    parse::{Parse, ParseStream},
+    spanned::Spanned,
+    Expr, Ident, LitStr, Path, Token,
+};
+
+use crate::validate::is_void;
+
+pub enum Node {
+    Element(Element),
+    Component(Component),
+    Text(LitStr),
+    Expr(Expr),
+}
+
+pub struct Element {
+    pub tag: Name,
+    pub attributes: Vec<Attribute>,
+    pub children: Vec<Node>,
+}
+
+// A component tag, like `<Button text="Click me!" />`
+pub struct Component {
+    pub path: Path,
+    pub attributes: Vec<Attribute>,
+}
+
+pub struct Attribute {
+    // The prefix before the colon, as in `on:click`
+    pub prefix: Option<Name>,
+    pub name: Name,
+    pub value: Value,
+}
+
+pub enum Value {
+    None,
+    Lit(LitStr),
+    Expr(Expr),
+}
+
+// A name that may contain dashes, like `data-index` or `my-element`
+pub struct Name {
+    pub value: String,
+    pub span: Span,
+}
+
+impl Parse for Node {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let node = parse_node(input)?;
+        if !input.is_empty() {
+            return Err(input.error("expected a single root element"));
+        }
+        Ok(node)
+    }
+}
+
+fn parse_node(input: ParseStream) -> syn::Result<Node> {
+    if input.peek(Token![<]) {
+        parse_element(input)
+    } else if input.peek(LitStr) {
+        Ok(Node::Text(input.parse()?))
+    } else if input.peek(syn::token::Brace) {
+        let content;
+        braced!(content in input);
+        Ok(Node::Expr(content.parse()?))
+    } else {
+        Err(input.error("expected an element, a string literal or an `{expression}`"))
+    }
+}
+
+fn parse_element(input: ParseStream) -> syn::Result<Node> {
+    input.parse::<Token![<]>()?;
+
+    // Capitalized tags are components
+    if input.peek(Ident) && starts_uppercase(&input.fork().call(Ident::parse_any)?) {
+        let path: Path = input.parse()?;
+        let attributes = parse_attributes(input)?;
+        if !input.peek(Token![/]) {
+            return Err(syn::Error::new(
+                path.span(),
+                "components cannot have children, close the tag with `/>`",
+            ));
+        }
+        input.parse::<Token![/]>()?;
+        input.parse::<Token![>]>()?;
+        return Ok(Node::Component(Component { path, attributes }));
+    }
+
+    let tag = parse_name(input)?;
+    let attributes = parse_attributes(input)?;
+    if input.peek(Token![/]) {
+        input.parse::<Token![/]>()?;
+        input.parse::<Token![>]>()?;
+        return Ok(Node::Element(Element {
+            tag,
+            attributes,
+            children: Vec::new(),
+        }));
+    }
+    input.parse::<Token![>]>()?;
+
+    // Void elements have no closing tag
+    if is_void(&tag.value) {
+        return Ok(Node::Element(Element {
+            tag,
+            attributes,
+            children: Vec::new(),
+        }));
+    }
+
+    let mut children = Vec::new();
+    loop {
+        if input.is_empty() {
+            return Err(syn::Error::new(
+                tag.span,
+                format!("unclosed tag, expected `</{}>`", tag.value),
+            ));
+        }
+        if input.peek(Token![<]) && input.peek2(Token![/]) {
+            break;
+        }
+        children.push(parse_node(input)?);
+    }
+
+    input.parse::<Token![<]>()?;
+    input.parse::<Token![/]>()?;
+    let closing = parse_name(input)?;
+    if closing.value != tag.value {
+        return Err(syn::Error::new(
+            closing.span,
+            format!(
+                "closing tag `</{}>` does not match `<{}>`",
+                closing.value, tag.value
+            ),
+        ));
+    }
+    input.parse::<Token![>]>()?;
+
+    Ok(Node::Element(Element {
+        tag,
+        attributes,
+        children,
+    }))
+}
+
+fn parse_attributes(input: ParseStream) -> syn::Result<Vec<Attribute>> {
+    let mut attributes = Vec::new();
+    while !input.peek(Token![>]) && !input.peek(Token![/]) {
+        if input.is_empty() {
+            return Err(input.error("expected `>` or `/>`"));
+        }
+        let mut name = parse_name(input)?;
+        let mut prefix = None;
+        if input.peek(Token![:]) && !input.peek(Token![::]) {
+            input.parse::<Token![:]>()?;
+            prefix = Some(name);
+            name = parse_name(input)?;
+        }
+        let value = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            if input.peek(LitStr) {
+                Value::Lit(input.parse()?)
+            } else if input.peek(syn::token::Brace) {
+                let content;
+                braced!(content in input);
+                Value::Expr(content.parse()?)
+            } else {
+                return Err(input.error("expected a string literal or an `{expression}`"));
+            }
+        } else {
+            Value::None
+        };
+        attributes.push(Attribute {
+            prefix,
+            name,
+            value,
+        });
+    }
+    Ok(attributes)
+}
+
+fn parse_name(input: ParseStream) -> syn::Result<Name> {
+    let first = input.call(Ident::parse_any)?;
+    let mut value = first.unraw().to_string();
+    let mut span = first.span();
+    while input.peek(Token![-]) {
+        input.parse::<Token![-]>()?;
+        let part = input.call(Ident::parse_any)?;
+        value.push('-');
+        value.push_str(&part.unraw().to_string());
+        span = span.join(part.span()).unwrap_or(span);
+    }
+    Ok(Name { value, span })
+}
+
+fn starts_uppercase(ident: &Ident) -> bool {
+    ident
+        .to_string()
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_uppercase())
+}
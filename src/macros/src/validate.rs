@@ -0,0 +1,444 @@
+use syn::{spanned::Spanned, Error};
+
+use crate::parse::{Attribute, Component, Element, Name, Node, Value};
+
+// https://html.spec.whatwg.org/multipage/indices.html#elements-3
+const TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "address",
+    "area",
+    "article",
+    "aside",
+    "audio",
+    "b",
+    "base",
+    "bdi",
+    "bdo",
+    "blockquote",
+    "body",
+    "br",
+    "button",
+    "canvas",
+    "caption",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "data",
+    "datalist",
+    "dd",
+    "del",
+    "details",
+    "dfn",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "i",
+    "iframe",
+    "img",
+    "input",
+    "ins",
+    "kbd",
+    "label",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "map",
+    "mark",
+    "menu",
+    "meta",
+    "meter",
+    "nav",
+    "noscript",
+    "object",
+    "ol",
+    "optgroup",
+    "option",
+    "output",
+    "p",
+    "picture",
+    "pre",
+    "progress",
+    "q",
+    "rp",
+    "rt",
+    "ruby",
+    "s",
+    "samp",
+    "script",
+    "search",
+    "section",
+    "select",
+    "slot",
+    "small",
+    "source",
+    "span",
+    "strong",
+    "style",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "time",
+    "title",
+    "tr",
+    "track",
+    "u",
+    "ul",
+    "var",
+    "video",
+    "wbr",
+];
+
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+// https://html.spec.whatwg.org/multipage/dom.html#global-attributes
+const GLOBAL_ATTRIBUTES: &[&str] = &[
+    "accesskey",
+    "autocapitalize",
+    "autofocus",
+    "class",
+    "contenteditable",
+    "dir",
+    "draggable",
+    "enterkeyhint",
+    "hidden",
+    "id",
+    "inert",
+    "inputmode",
+    "is",
+    "lang",
+    "nonce",
+    "popover",
+    "role",
+    "slot",
+    "spellcheck",
+    "style",
+    "tabindex",
+    "title",
+    "translate",
+];
+
+// https://html.spec.whatwg.org/multipage/indices.html#attributes-3
+const ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("accept", &["input"]),
+    ("accept-charset", &["form"]),
+    ("action", &["form"]),
+    ("allow", &["iframe"]),
+    ("alt", &["area", "img", "input"]),
+    ("async", &["script"]),
+    ("autocomplete", &["form", "input", "select", "textarea"]),
+    ("autoplay", &["audio", "video"]),
+    ("charset", &["meta"]),
+    ("checked", &["input"]),
+    ("cite", &["blockquote", "del", "ins", "q"]),
+    ("cols", &["textarea"]),
+    ("colspan", &["td", "th"]),
+    ("content", &["meta"]),
+    ("controls", &["audio", "video"]),
+    ("coords", &["area"]),
+    ("crossorigin", &["audio", "img", "link", "script", "video"]),
+    ("datetime", &["del", "ins", "time"]),
+    ("decoding", &["img"]),
+    ("default", &["track"]),
+    ("defer", &["script"]),
+    (
+        "disabled",
+        &[
+            "button", "fieldset", "input", "optgroup", "option", "select", "textarea",
+        ],
+    ),
+    ("download", &["a", "area"]),
+    ("enctype", &["form"]),
+    ("for", &["label", "output"]),
+    (
+        "form",
+        &[
+            "button", "fieldset", "input", "object", "output", "select", "textarea",
+        ],
+    ),
+    ("formaction", &["button", "input"]),
+    ("formmethod", &["button", "input"]),
+    ("formnovalidate", &["button", "input"]),
+    ("headers", &["td", "th"]),
+    (
+        "height",
+        &[
+            "canvas", "embed", "iframe", "img", "input", "object", "source", "video",
+        ],
+    ),
+    ("high", &["meter"]),
+    ("href", &["a", "area", "base", "link"]),
+    ("hreflang", &["a", "link"]),
+    ("http-equiv", &["meta"]),
+    ("integrity", &["link", "script"]),
+    ("kind", &["track"]),
+    ("label", &["optgroup", "option", "track"]),
+    ("list", &["input"]),
+    ("loading", &["iframe", "img"]),
+    ("loop", &["audio", "video"]),
+    ("low", &["meter"]),
+    ("max", &["input", "meter", "progress"]),
+    ("maxlength", &["input", "textarea"]),
+    ("media", &["link", "meta", "source", "style"]),
+    ("method", &["form"]),
+    ("min", &["input", "meter"]),
+    ("minlength", &["input", "textarea"]),
+    ("multiple", &["input", "select"]),
+    ("muted", &["audio", "video"]),
+    (
+        "name",
+        &[
+            "button", "fieldset", "form", "iframe", "input", "map", "meta", "object", "output",
+            "select", "slot", "textarea",
+        ],
+    ),
+    ("novalidate", &["form"]),
+    ("open", &["details", "dialog"]),
+    ("optimum", &["meter"]),
+    ("pattern", &["input"]),
+    ("placeholder", &["input", "textarea"]),
+    ("poster", &["video"]),
+    ("preload", &["audio", "video"]),
+    ("readonly", &["input", "textarea"]),
+    (
+        "referrerpolicy",
+        &["a", "area", "iframe", "img", "link", "script"],
+    ),
+    ("rel", &["a", "area", "form", "link"]),
+    ("required", &["input", "select", "textarea"]),
+    ("reversed", &["ol"]),
+    ("rows", &["textarea"]),
+    ("rowspan", &["td", "th"]),
+    ("sandbox", &["iframe"]),
+    ("scope", &["th"]),
+    ("selected", &["option"]),
+    ("shape", &["area"]),
+    ("size", &["input", "select"]),
+    ("sizes", &["img", "link", "source"]),
+    ("span", &["col", "colgroup"]),
+    (
+        "src",
+        &[
+            "audio", "embed", "iframe", "img", "input", "script", "source", "track", "video",
+        ],
+    ),
+    ("srcdoc", &["iframe"]),
+    ("srclang", &["track"]),
+    ("srcset", &["img", "source"]),
+    ("start", &["ol"]),
+    ("step", &["input"]),
+    ("target", &["a", "area", "base", "form"]),
+    (
+        "type",
+        &[
+            "a", "button", "embed", "input", "link", "object", "ol", "script", "source",
+        ],
+    ),
+    ("usemap", &["img"]),
+    (
+        "value",
+        &[
+            "button", "data", "input", "li", "meter", "option", "output", "progress", "textarea",
+        ],
+    ),
+    (
+        "width",
+        &[
+            "canvas", "embed", "iframe", "img", "input", "object", "source", "video",
+        ],
+    ),
+    ("wrap", &["textarea"]),
+];
+
+pub fn is_void(tag: &str) -> bool {
+    VOID_TAGS.contains(&tag)
+}
+
+pub fn validate(root: &Node) -> syn::Result<()> {
+    match root {
+        Node::Element(element) => validate_element(element),
+        Node::Component(component) => validate_component(component),
+        Node::Text(text) => Err(Error::new(
+            text.span(),
+            "expected an element or a component at the root",
+        )),
+        Node::Expr(expr) => Err(Error::new(
+            expr.span(),
+            "expected an element or a component at the root",
+        )),
+    }
+}
+
+fn validate_element(element: &Element) -> syn::Result<()> {
+    let tag = &element.tag;
+    // Custom elements contain a dash
+    let custom = tag.value.contains('-');
+    // Elements are created in the HTML namespace, so SVG and MathML would not render
+    if tag.value == "svg" || tag.value == "math" {
+        return Err(Error::new(
+            tag.span,
+            format!(
+                "`<{}>` is not supported, only HTML elements can be created",
+                tag.value
+            ),
+        ));
+    }
+    if !custom && !TAGS.contains(&tag.value.as_str()) {
+        return Err(Error::new(
+            tag.span,
+            format!("unknown tag `<{}>`", tag.value),
+        ));
+    }
+    for attribute in &element.attributes {
+        validate_attribute(&tag.value, custom, attribute)?;
+    }
+    for child in &element.children {
+        match child {
+            Node::Element(child) => validate_element(child)?,
+            Node::Component(child) => validate_component(child)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn validate_component(component: &Component) -> syn::Result<()> {
+    for attribute in &component.attributes {
+        if let Some(prefix) = &attribute.prefix {
+            return Err(Error::new(
+                prefix.span,
+                "components only take plain attributes, which are the fields of their props",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_attribute(tag: &str, custom: bool, attribute: &Attribute) -> syn::Result<()> {
+    let name = &attribute.name;
+    let Some(prefix) = &attribute.prefix else {
+        return validate_attribute_name(tag, custom, name);
+    };
+    match prefix.value.as_str() {
+        "on" | "class" | "style" | "prop" => {}
+        "attr" => validate_attribute_name(tag, custom, name)?,
+        _ => {
+            return Err(Error::new(
+                prefix.span,
+                format!(
+                    "unknown prefix `{}:`, expected `on:`, `class:`, `style:`, `prop:` or `attr:`",
+                    prefix.value
+                ),
+            ))
+        }
+    }
+    match &attribute.value {
+        Value::Expr(_) => Ok(()),
+        _ => Err(Error::new(
+            name.span,
+            format!(
+                "`{}:{}` expects an `{{expression}}`",
+                prefix.value, name.value
+            ),
+        )),
+    }
+}
+
+fn validate_attribute_name(tag: &str, custom: bool, name: &Name) -> syn::Result<()> {
+    let value = name.value.as_str();
+    if custom
+        || GLOBAL_ATTRIBUTES.contains(&value)
+        || value.starts_with("data-")
+        || value.starts_with("aria-")
+    {
+        return Ok(());
+    }
+    match ATTRIBUTES.iter().find(|(attribute, _)| *attribute == value) {
+        Some((_, tags)) if tags.contains(&tag) => Ok(()),
+        Some(_) => Err(Error::new(
+            name.span,
+            format!("attribute `{}` is not allowed on `<{}>`", value, tag),
+        )),
+        None => Err(Error::new(
+            name.span,
+            format!("unknown attribute `{}`", value),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn check(input: &str) -> Result<(), String> {
+        let node: Node = syn::parse_str(input).map_err(|e| e.to_string())?;
+        validate(&node).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            check(r#"<div class="a" data-index={i} on:click={f}><input type="text" /></div>"#),
+            Ok(())
+        );
+        assert_eq!(check("<my-element foo=\"bar\"></my-element>"), Ok(()));
+        assert_eq!(check("<dvi></dvi>"), Err("unknown tag `<dvi>`".to_owned()));
+        assert_eq!(
+            check("<div><svg></svg></div>"),
+            Err("`<svg>` is not supported, only HTML elements can be created".to_owned())
+        );
+        assert_eq!(
+            check("<div><span hreff=\"/\"></span></div>"),
+            Err("unknown attribute `hreff`".to_owned())
+        );
+        assert_eq!(
+            check("<span href=\"/\"></span>"),
+            Err("attribute `href` is not allowed on `<span>`".to_owned())
+        );
+        assert_eq!(
+            check("<div on:click=\"f\"></div>"),
+            Err("`on:click` expects an `{expression}`".to_owned())
+        );
+        assert_eq!(
+            check("<div><p></div>"),
+            Err("closing tag `</div>` does not match `<p>`".to_owned())
+        );
+        assert_eq!(
+            check("<div></div><div></div>"),
+            Err("expected a single root element".to_owned())
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{
+    js::{self, JsValue, ObjectRef},
+    js_fn,
+};
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    fn __read_array(object_id: u32);
+}
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::__read_array;
+
+// Handle to a JS array. Reading the elements one by one calls JS for every element,
+// while `to_vec` copies all elements at once.
+#[derive(Debug, Clone)]
+pub struct Array(ObjectRef);
+
+impl Array {
+    pub fn new() -> Self {
+        js_fn!("return []").invoke(&[]).to_array().unwrap()
+    }
+
+    pub fn object(&self) -> &ObjectRef {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        js_fn!("return {}.length")
+            .invoke(&[self.into()])
+            .to_num()
+            .unwrap_or(0.0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> JsValue {
+        js_fn!("return {}[{}]").invoke(&[self.into(), index.into()])
+    }
+
+    pub fn push(&self, value: impl Into<JsValue>) {
+        js_fn!("{}.push({})").queue(&[self.into(), value.into()]);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = JsValue> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    // Copies the elements of the array. Nested arrays and objects are still referenced.
+    pub fn to_vec(&self) -> Vec<JsValue> {
+        js::prepare_call();
+        unsafe { __read_array(self.0.id()) };
+
+        match js::expect_response(js::read_response()) {
+            JsValue::List(values) => values,
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Default for Array {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<ObjectRef> for Array {
+    fn from(object: ObjectRef) -> Self {
+        Self(object)
+    }
+}
+
+impl From<Array> for JsValue {
+    fn from(array: Array) -> Self {
+        Self::Array(array.0)
+    }
+}
+
+impl From<&Array> for JsValue {
+    fn from(array: &Array) -> Self {
+        Self::Array(array.0.clone())
+    }
+}
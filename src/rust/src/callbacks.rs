@@ -1,40 +1,374 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::cell::RefCell;
 
-use crate::{console_error, js, js::ObjectRef};
+use crate::{commands, console_error, js::ObjectRef};
+
+type Callback = Box<dyn FnMut(ObjectRef) + 'static>;
+
+// A callback is temporarily taken out of its listener while it is being called.
+// The callback of a `once` listener is freed after the first call, but its id stays
+// reserved until the `ListenerHandle` is dropped. Likewise, a listener that is removed
+// while its callback is running keeps its id until the call returns, so that the id
+// cannot be reused by a listener that is added in the meantime.
+struct Listener {
+    callback: Option<Callback>,
+    once: bool,
+    running: bool,
+    removed: bool,
+}
 
 thread_local! {
-    // Hashmap used to store all callbacks.
-    static CALLBACKS: RefCell<HashMap<u32, Box<dyn FnMut(ObjectRef) + 'static>>> = RefCell::new(HashMap::new());
+    // Table used to store all callbacks.
+    static CALLBACKS: RefCell<Slab<Listener>> = const { RefCell::new(Slab::new()) };
+}
+
+// Values indexed by an id of type u32. Similar to the `objects` and `free` arrays
+// in `main.js`, the ids of removed values are reused for new values.
+pub(crate) struct Slab<T> {
+    entries: Vec<Option<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Slab<T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, value: T) -> u32 {
+        match self.free.pop() {
+            Some(id) => {
+                self.entries[id as usize] = Some(value);
+                id
+            }
+            None => {
+                self.entries.push(Some(value));
+                (self.entries.len() - 1) as u32
+            }
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, id: u32) -> Option<&mut T> {
+        self.entries.get_mut(id as usize).and_then(Option::as_mut)
+    }
+
+    pub(crate) fn remove(&mut self, id: u32) -> Option<T> {
+        let value = self.entries.get_mut(id as usize).and_then(Option::take);
+        if value.is_some() {
+            self.free.push(id);
+        }
+        value
+    }
 }
 
+#[cfg(target_arch = "wasm32")]
 extern "C" {
-    fn __add_event_listener(object_id: u32, e_ptr: *const u8, e_len: u32, callback_id: u32);
+    fn __add_event_listener(
+        object_id: u32,
+        e_ptr: *const u8,
+        e_len: u32,
+        callback_id: u32,
+        flags: u32,
+        signal_id: u32,
+    );
+    fn __remove_event_listener(callback_id: u32);
 }
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::{__add_event_listener, __remove_event_listener};
 
 #[no_mangle]
 pub fn call_callback(id: u32, event_id: u32) {
-    CALLBACKS.with_borrow_mut(|map| match map.get_mut(&id) {
-        None => {
-            console_error!("could not find callback with id {}", id);
+    // Take the callback out of the table, so that it can add or remove listeners itself
+    let callback = CALLBACKS.with_borrow_mut(|slab| {
+        let listener = slab.get_mut(id)?;
+        let callback = listener.callback.take()?;
+        listener.running = true;
+        Some(callback)
+    });
+    let Some(mut f) = callback else {
+        console_error!("could not find callback with id {}", id);
+        return;
+    };
+
+    f(ObjectRef::new(event_id));
+
+    // Put the callback back, unless its listener was removed in the meantime
+    let f = CALLBACKS.with_borrow_mut(|slab| {
+        let listener = slab.get_mut(id)?;
+        listener.running = false;
+        if listener.removed {
+            slab.remove(id);
+            return Some(f);
+        }
+        if !listener.once {
+            listener.callback = Some(f);
+            return None;
+        }
+        Some(f)
+    });
+    // NOTE: the callback is dropped outside of the borrow, since it may own listener handles
+    drop(f);
+
+    commands::flush_or_log();
+}
+
+// Handle to an event listener. When the handle is dropped, the listener
+// is removed from its target and the callback is freed.
+#[derive(Debug)]
+pub struct ListenerHandle {
+    callback_id: u32,
+}
+
+impl ListenerHandle {
+    pub fn remove(self) {
+        drop(self);
+    }
+
+    // Keeps the listener attached for the rest of the lifetime of the page.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for ListenerHandle {
+    fn drop(&mut self) {
+        unsafe { __remove_event_listener(self.callback_id) };
+        let listener = CALLBACKS.with_borrow_mut(|slab| match slab.get_mut(self.callback_id) {
+            Some(listener) if listener.running => {
+                listener.removed = true;
+                None
+            }
+            _ => slab.remove(self.callback_id),
+        });
+        drop(listener);
+    }
+}
+
+// Options of `addEventListener`, see
+// https://developer.mozilla.org/en-US/docs/Web/API/EventTarget/addEventListener#options
+#[derive(Debug, Clone, Default)]
+pub struct EventOptions {
+    once: bool,
+    passive: bool,
+    capture: bool,
+    signal: Option<ObjectRef>,
+}
+
+impl EventOptions {
+    const ONCE: u32 = 1 << 0;
+    const PASSIVE: u32 = 1 << 1;
+    const CAPTURE: u32 = 1 << 2;
+    const SIGNAL: u32 = 1 << 3;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn once(mut self, once: bool) -> Self {
+        self.once = once;
+        self
+    }
+
+    pub fn passive(mut self, passive: bool) -> Self {
+        self.passive = passive;
+        self
+    }
+
+    pub fn capture(mut self, capture: bool) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    // The listener is removed when the given `AbortSignal` is aborted.
+    pub fn signal(mut self, signal: &ObjectRef) -> Self {
+        self.signal = Some(signal.to_owned());
+        self
+    }
+
+    fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.once {
+            flags |= Self::ONCE;
         }
-        Some(f) => (*f)(ObjectRef::new(event_id)),
-    })
+        if self.passive {
+            flags |= Self::PASSIVE;
+        }
+        if self.capture {
+            flags |= Self::CAPTURE;
+        }
+        if self.signal.is_some() {
+            flags |= Self::SIGNAL;
+        }
+        flags
+    }
+}
+
+// Frees the callback of a listener that can no longer be called, without removing the listener.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn free_callback(callback_id: u32) {
+    let listener = CALLBACKS.with_borrow_mut(|slab| slab.remove(callback_id));
+    drop(listener);
 }
 
 pub fn add_event_listener(
     object: &ObjectRef,
     event: &str,
     callback: impl FnMut(ObjectRef) + 'static,
-) {
+) -> ListenerHandle {
+    add_event_listener_with_options(object, event, &EventOptions::default(), callback)
+}
+
+pub fn add_event_listener_with_options(
+    object: &ObjectRef,
+    event: &str,
+    options: &EventOptions,
+    callback: impl FnMut(ObjectRef) + 'static,
+) -> ListenerHandle {
     // Store callback with a new id
-    let callback_id = CALLBACKS.with_borrow_mut(|map| {
-        let id = map.len() as u32;
-        map.insert(id, Box::new(callback));
-        id
+    let callback_id = CALLBACKS.with_borrow_mut(|slab| {
+        slab.insert(Listener {
+            callback: Some(Box::new(callback)),
+            once: options.once,
+            running: false,
+            removed: false,
+        })
     });
 
     // Call JS
+    let signal_id = options.signal.as_ref().map_or(0, ObjectRef::id);
     unsafe {
-        __add_event_listener(object.id(), event.as_ptr(), event.len() as u32, callback_id);
+        __add_event_listener(
+            object.id(),
+            event.as_ptr(),
+            event.len() as u32,
+            callback_id,
+            options.flags(),
+            signal_id,
+        );
+    }
+
+    ListenerHandle { callback_id }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::rc::Rc;
+
+    fn log_callback(
+        logs: &Rc<RefCell<Vec<&'static str>>>,
+        name: &'static str,
+    ) -> impl FnMut(ObjectRef) {
+        let logs = logs.clone();
+        move |_| logs.borrow_mut().push(name)
+    }
+
+    #[test]
+    fn test_callback_ids() {
+        let logs: Rc<RefCell<Vec<&str>>> = Default::default();
+        let object = ObjectRef::new(0);
+
+        // register callbacks
+        let a = add_event_listener(&object, "click", log_callback(&logs, "a"));
+        let b = add_event_listener(&object, "click", log_callback(&logs, "b"));
+        assert_ne!(a.callback_id, b.callback_id);
+
+        // remove and re-register
+        let a_id = a.callback_id;
+        a.remove();
+        let c = add_event_listener(&object, "click", log_callback(&logs, "c"));
+        assert_eq!(c.callback_id, a_id);
+
+        // check dispatch
+        call_callback(b.callback_id, 0);
+        call_callback(c.callback_id, 0);
+        call_callback(a_id, 0);
+        assert_eq!(*logs.borrow(), vec!["b", "c", "c"]);
+
+        // removed callbacks are not called anymore
+        let b_id = b.callback_id;
+        drop(b);
+        call_callback(b_id, 0);
+        assert_eq!(*logs.borrow(), vec!["b", "c", "c"]);
+    }
+
+    #[test]
+    fn test_callback_removes_itself() {
+        let logs: Rc<RefCell<Vec<&str>>> = Default::default();
+        let object = ObjectRef::new(0);
+
+        // the handle is dropped by the callback itself
+        let handle: Rc<RefCell<Option<ListenerHandle>>> = Default::default();
+        let handle_clone = handle.clone();
+        let mut log = log_callback(&logs, "once");
+        *handle.borrow_mut() = Some(add_event_listener(&object, "click", move |event| {
+            log(event);
+            handle_clone.borrow_mut().take();
+        }));
+        let id = handle.borrow().as_ref().unwrap().callback_id;
+
+        call_callback(id, 0);
+        call_callback(id, 0);
+        assert_eq!(*logs.borrow(), vec!["once"]);
+    }
+
+    #[test]
+    fn test_callback_replaces_itself() {
+        let logs: Rc<RefCell<Vec<&str>>> = Default::default();
+        let object = ObjectRef::new(0);
+
+        // the callback removes its own listener, and adds a new one
+        let handle: Rc<RefCell<Option<ListenerHandle>>> = Default::default();
+        let handle_clone = handle.clone();
+        let logs_clone = logs.clone();
+        let mut log = log_callback(&logs, "first");
+        let target = object.clone();
+        *handle.borrow_mut() = Some(add_event_listener(&object, "click", move |event| {
+            log(event);
+            handle_clone.borrow_mut().take();
+            let new = add_event_listener(&target, "click", log_callback(&logs_clone, "second"));
+            *handle_clone.borrow_mut() = Some(new);
+        }));
+        let first = handle.borrow().as_ref().unwrap().callback_id;
+        call_callback(first, 0);
+        assert_eq!(*logs.borrow(), vec!["first"]);
+
+        // the new listener does not reuse the id of the running callback
+        let second = handle.borrow().as_ref().unwrap().callback_id;
+        assert_ne!(first, second);
+        call_callback(second, 0);
+        call_callback(first, 0);
+        assert_eq!(*logs.borrow(), vec!["first", "second"]);
+
+        // the id is freed after the call
+        let other = add_event_listener(&object, "click", |_| {});
+        assert_eq!(other.callback_id, first);
+    }
+
+    #[test]
+    fn test_once_callback() {
+        let logs: Rc<RefCell<Vec<&str>>> = Default::default();
+        let object = ObjectRef::new(0);
+
+        // register once listener
+        let options = EventOptions::new().once(true);
+        let handle = add_event_listener_with_options(
+            &object,
+            "click",
+            &options,
+            log_callback(&logs, "once"),
+        );
+
+        // callback is freed after the first call
+        call_callback(handle.callback_id, 0);
+        call_callback(handle.callback_id, 0);
+        assert_eq!(*logs.borrow(), vec!["once"]);
+        assert_eq!(Rc::strong_count(&logs), 1);
+
+        // id stays reserved until the handle is dropped
+        let other = add_event_listener(&object, "click", log_callback(&logs, "other"));
+        assert_ne!(other.callback_id, handle.callback_id);
     }
 }
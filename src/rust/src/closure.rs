@@ -0,0 +1,308 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use crate::{
+    callbacks::Slab,
+    commands, console_error,
+    js::{self, JsValue},
+};
+
+type ClosureFn = Box<dyn FnMut(Vec<JsValue>) -> JsValue + 'static>;
+
+// A closure is temporarily taken out of its entry while it is being called. A closure that
+// is freed while it is running keeps its id until the call returns, so that the id cannot be
+// reused by a closure that is created in the meantime.
+struct Entry {
+    closure: Option<ClosureFn>,
+    once: bool,
+    running: bool,
+    removed: bool,
+    // Shared with the handles of the closure, and set when it is freed
+    freed: Rc<Cell<bool>>,
+}
+
+thread_local! {
+    // Table used to store all closures that can be called from JS.
+    static CLOSURES: RefCell<Slab<Entry>> = const { RefCell::new(Slab::new()) };
+}
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    fn __closure_return(r_ptr: *const u8, r_len: u32);
+    fn __free_closure(closure_id: u32);
+}
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::{__closure_return, __free_closure};
+
+// Called by the JS function of a closure. The arguments are written to the allocation
+// buffer by JS, and the result is passed back with `__closure_return`.
+#[no_mangle]
+pub fn call_closure(id: u32) {
+    let args = js::read_values();
+
+    // Take the closure out of the table, so that it can create or drop closures itself
+    let closure = CLOSURES.with_borrow_mut(|slab| {
+        let entry = slab.get_mut(id)?;
+        let closure = entry.closure.take()?;
+        entry.running = true;
+        Some(closure)
+    });
+    let result = match closure {
+        Some(mut f) => {
+            let result = f(args);
+
+            // Put the closure back, unless it was freed in the meantime or can only be called once
+            let (f, once) = CLOSURES.with_borrow_mut(|slab| {
+                let Some(entry) = slab.get_mut(id) else {
+                    return (Some(f), false);
+                };
+                entry.running = false;
+                if entry.removed {
+                    slab.remove(id);
+                    return (Some(f), false);
+                }
+                if entry.once {
+                    entry.freed.set(true);
+                    slab.remove(id);
+                    return (Some(f), true);
+                }
+                entry.closure = Some(f);
+                (None, false)
+            });
+            // NOTE: the closure is dropped outside of the borrow, since it may own closures
+            drop(f);
+            if once {
+                unsafe { __free_closure(id) };
+            }
+            result
+        }
+        None => {
+            console_error!("could not find closure with id {}", id);
+            JsValue::Undefined
+        }
+    };
+
+    commands::flush_or_log();
+
+    // The result is kept alive until JS has read it
+    let buffer = js::serialize(std::slice::from_ref(&result));
+    unsafe { __closure_return(buffer.as_ptr(), buffer.len() as u32) };
+}
+
+fn free(id: u32) {
+    unsafe { __free_closure(id) };
+    let entry = CLOSURES.with_borrow_mut(|slab| match slab.get_mut(id) {
+        Some(entry) if entry.running => {
+            entry.freed.set(true);
+            entry.removed = true;
+            None
+        }
+        _ => slab.remove(id).inspect(|entry| entry.freed.set(true)),
+    });
+    drop(entry);
+}
+
+#[derive(Debug)]
+struct ClosureId {
+    id: u32,
+    once: bool,
+    freed: Rc<Cell<bool>>,
+}
+
+impl ClosureId {
+    // The id may already belong to another closure once this one is freed
+    fn free(&self) {
+        if !self.freed.get() {
+            free(self.id);
+        }
+    }
+}
+
+impl Drop for ClosureId {
+    fn drop(&mut self) {
+        // A one-shot closure is only freed when it is called, or released
+        if !self.once {
+            self.free();
+        }
+    }
+}
+
+// A Rust closure that is passed to JS as a function, for example to `setTimeout`
+// or `Promise.then`. JS calls it with all arguments and receives its return value.
+//
+// A closure created with `new` is freed when all of its handles are dropped, after which
+// calling it from JS does nothing. A closure created with `once` is freed after its first call
+// instead, so its handles can be dropped right away, and `release` frees it if it will never be
+// called. Use `forget` to never free a closure.
+#[derive(Debug, Clone)]
+pub struct Closure(Rc<ClosureId>);
+
+impl Closure {
+    pub fn new(closure: impl FnMut(Vec<JsValue>) -> JsValue + 'static) -> Self {
+        Self::insert(Box::new(closure), false)
+    }
+
+    pub fn once(closure: impl FnOnce(Vec<JsValue>) -> JsValue + 'static) -> Self {
+        let mut closure = Some(closure);
+        Self::insert(
+            Box::new(move |args| closure.take().map_or(JsValue::Undefined, |f| f(args))),
+            true,
+        )
+    }
+
+    fn insert(closure: ClosureFn, once: bool) -> Self {
+        let freed = Rc::new(Cell::new(false));
+        let id = CLOSURES.with_borrow_mut(|slab| {
+            slab.insert(Entry {
+                closure: Some(closure),
+                once,
+                running: false,
+                removed: false,
+                freed: freed.clone(),
+            })
+        });
+        Self(Rc::new(ClosureId { id, once, freed }))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    // Keeps the closure alive for the rest of the lifetime of the page.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+
+    // Frees the closure right away, also if it was created with `once` and not called yet,
+    // for example after `clearTimeout`. Calling it from JS afterwards does nothing.
+    pub fn release(self) {
+        self.0.free();
+    }
+}
+
+impl From<Closure> for JsValue {
+    fn from(closure: Closure) -> Self {
+        Self::Function(closure)
+    }
+}
+
+impl From<&Closure> for JsValue {
+    fn from(closure: &Closure) -> Self {
+        Self::Function(closure.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{array::Array, native};
+
+    #[test]
+    fn test_closure() {
+        let sum = Closure::new(|args| {
+            let sum: f64 = args.into_iter().filter_map(|x| x.to_num().ok()).sum();
+            sum.into()
+        });
+        let result = native::call_closure(&sum, &[1.into(), 2.into(), 3.5.into()]);
+        assert_eq!(result.to_num(), Ok(6.5));
+        let result = native::call_closure(&sum, &[]);
+        assert_eq!(result.to_num(), Ok(0.0));
+
+        // dropped closures are not called anymore
+        let id = sum.id();
+        drop(sum);
+        assert!(CLOSURES.with_borrow_mut(|slab| slab.get_mut(id).is_none()));
+    }
+
+    #[test]
+    fn test_once_closure() {
+        let calls = Rc::new(Cell::new(0));
+        let calls_clone = calls.clone();
+        let closure = Closure::once(move |_| {
+            calls_clone.set(calls_clone.get() + 1);
+            "done".into()
+        });
+        let id = closure.id();
+
+        // handles can be dropped before the call
+        let handle = closure.clone();
+        drop(closure);
+        assert_eq!(
+            native::call_closure(&handle, &[]).to_string(),
+            Ok("done".to_owned())
+        );
+        assert!(matches!(
+            native::call_closure(&handle, &[]),
+            JsValue::Undefined
+        ));
+        assert_eq!(calls.get(), 1);
+        assert!(CLOSURES.with_borrow_mut(|slab| slab.get_mut(id).is_none()));
+    }
+
+    #[test]
+    fn test_closure_replaces_itself() {
+        // the closure drops its own handle, and creates a new closure
+        let handle: Rc<RefCell<Option<Closure>>> = Default::default();
+        let handle_clone = handle.clone();
+        *handle.borrow_mut() = Some(Closure::new(move |_| {
+            handle_clone.borrow_mut().take();
+            let new = Closure::new(|_| "second".into());
+            *handle_clone.borrow_mut() = Some(new);
+            "first".into()
+        }));
+        let first = handle.borrow().as_ref().unwrap().id();
+
+        // call from JS, which does not keep the closure alive
+        let functions = Array::new();
+        functions.push(handle.borrow().as_ref().unwrap());
+        let function = functions.get(0).to_ref().unwrap();
+        let call = || native::call_function(&function, &[]);
+        assert_eq!(call().to_string(), Ok("first".to_owned()));
+
+        // the new closure does not reuse the id of the running closure
+        let second = handle.borrow().clone().unwrap();
+        assert_ne!(first, second.id());
+        assert_eq!(
+            native::call_closure(&second, &[]).to_string(),
+            Ok("second".to_owned())
+        );
+        assert!(matches!(call(), JsValue::Undefined));
+
+        // the id is freed after the call
+        let other = Closure::new(|_| JsValue::Undefined);
+        assert_eq!(other.id(), first);
+    }
+
+    #[test]
+    fn test_release_closure() {
+        // a one-shot closure that is never called is freed by `release`
+        let closure = Closure::once(|_| "done".into());
+        let id = closure.id();
+        let handle = closure.clone();
+        closure.release();
+        assert!(CLOSURES.with_borrow_mut(|slab| slab.get_mut(id).is_none()));
+        assert!(matches!(
+            native::call_closure(&handle, &[]),
+            JsValue::Undefined
+        ));
+
+        // closures are not freed again, once their id belongs to another closure
+        let closure = Closure::new(|_| JsValue::Undefined);
+        let id = closure.id();
+        let handle = closure.clone();
+        closure.release();
+        let called = Closure::once(|_| JsValue::Undefined);
+        native::call_closure(&called, &[]);
+        let other = Closure::new(|_| "other".into());
+        assert_eq!(other.id(), id);
+        drop(handle);
+        called.release();
+        assert_eq!(
+            native::call_closure(&other, &[]).to_string(),
+            Ok("other".to_owned())
+        );
+    }
+}
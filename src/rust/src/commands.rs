@@ -0,0 +1,190 @@
+use std::cell::{Cell, RefCell};
+
+use crate::js::{self, JsError, JsValue, Snippet};
+
+// Opt-in batching of calls that do not return a value, such as most DOM operations.
+// While batching, `Snippet::queue` appends calls to a command buffer instead of calling JS.
+// The buffer is sent to JS in a single call at the end of an event handler, after running
+// tasks, on the next animation frame, or right before a call that needs a return value.
+thread_local! {
+    static BATCHING: Cell<bool> = const { Cell::new(false) };
+    // Queued calls: the snippet id, the length of the params and the serialized params.
+    static COMMANDS: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static COUNT: Cell<u32> = const { Cell::new(0) };
+    // Objects and closures used by the queued calls, which are kept alive until the calls are flushed.
+    static RETAINED: RefCell<Vec<JsValue>> = const { RefCell::new(Vec::new()) };
+    // Objects dropped while batching. They are freed by JS after the queued calls.
+    static FREED: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+    // Whether JS has already been asked to call `flush_commands` on the next animation frame.
+    static FLUSH_REQUESTED: Cell<bool> = const { Cell::new(false) };
+}
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    fn __flush_commands(c_ptr: *const u8, c_len: u32, f_ptr: *const u32, f_len: u32);
+    fn __request_flush();
+}
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::{__flush_commands, __request_flush};
+
+pub fn set_batching(enabled: bool) {
+    if !enabled {
+        flush();
+    }
+    BATCHING.set(enabled);
+}
+
+pub fn is_batching() -> bool {
+    BATCHING.get()
+}
+
+// Called by JS on the next animation frame after a call was queued.
+#[no_mangle]
+pub fn flush_commands() {
+    FLUSH_REQUESTED.set(false);
+    flush_or_log();
+}
+
+// Sends the queued calls to JS. If a call throws, the following calls are skipped, the error
+// is logged to the console and Rust panics, like `js::invoke`.
+pub fn flush() {
+    if let Err(error) = try_flush() {
+        js::log_error(&error);
+        panic!("{}", error);
+    }
+}
+
+// Same as `flush`, but only logs the error. Used at the end of the functions that are
+// called by JS, such as event listeners, which cannot unwind into JS.
+pub(crate) fn flush_or_log() {
+    if let Err(error) = try_flush() {
+        js::log_error(&error);
+    }
+}
+
+// Same as `flush`, but returns the error thrown by a queued call.
+pub fn try_flush() -> Result<(), JsError> {
+    if COUNT.get() == 0 && FREED.with_borrow(Vec::is_empty) {
+        return Ok(());
+    }
+
+    let mut buffer = u32::to_le_bytes(COUNT.replace(0)).to_vec();
+    buffer.append(&mut COMMANDS.take());
+    // Dropping the retained objects adds them to `FREED`
+    drop(RETAINED.take());
+    let freed = FREED.take();
+
+    js::count_crossing();
+    unsafe {
+        __flush_commands(
+            buffer.as_ptr(),
+            buffer.len() as u32,
+            freed.as_ptr(),
+            freed.len() as u32,
+        )
+    };
+
+    js::read_response().map(drop)
+}
+
+pub(crate) fn queue(snippet: &Snippet, params: &[JsValue]) {
+    if !is_batching() {
+        snippet.invoke(params);
+        return;
+    }
+
+    let id = snippet.id();
+    let params_buffer = js::serialize(params);
+    COMMANDS.with_borrow_mut(|buffer| {
+        buffer.extend(u32::to_le_bytes(id));
+        buffer.extend(u32::to_le_bytes(params_buffer.len() as u32));
+        buffer.extend(params_buffer);
+    });
+    COUNT.set(COUNT.get() + 1);
+    RETAINED.with_borrow_mut(|retained| params.iter().for_each(|p| retain(retained, p)));
+    request_flush();
+}
+
+fn retain(retained: &mut Vec<JsValue>, value: &JsValue) {
+    match value {
+        JsValue::Object(object) => retained.push(JsValue::Object(object.clone())),
+        JsValue::Array(object) => retained.push(JsValue::Array(object.clone())),
+        JsValue::Function(closure) => retained.push(JsValue::Function(closure.clone())),
+        JsValue::List(values) => values.iter().for_each(|v| retain(retained, v)),
+        JsValue::Dict(entries) => entries.iter().for_each(|(_, v)| retain(retained, v)),
+        _ => {}
+    }
+}
+
+pub(crate) fn free_later(id: u32) {
+    FREED.with_borrow_mut(|freed| freed.push(id));
+    request_flush();
+}
+
+fn request_flush() {
+    if !FLUSH_REQUESTED.replace(true) {
+        js::count_crossing();
+        unsafe { __request_flush() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{element::Elem, js_fn, native};
+
+    fn build_tree(size: usize) {
+        let mut list = Elem::new("ul");
+        for i in 0..size {
+            let item = Elem::new("li")
+                .class("item")
+                .attr("data-index", &i.to_string())
+                .text("item");
+            list = list.append(&item);
+        }
+    }
+
+    // Counts the calls to JS, which are the cost of batching, rather than measuring time.
+    #[test]
+    fn test_batching_crossings() {
+        // without batching, every operation calls JS
+        let start = js::crossings();
+        build_tree(10_000);
+        let unbatched = js::crossings() - start;
+
+        // with batching, only creating the elements calls JS
+        set_batching(true);
+        let start = js::crossings();
+        build_tree(10_000);
+        flush();
+        let batched = js::crossings() - start;
+        set_batching(false);
+
+        assert!(unbatched >= 60_000);
+        assert!(batched <= 10_003);
+    }
+
+    #[test]
+    fn test_flush_error() {
+        set_batching(true);
+        let button = Elem::new("button").on("click", |_| {
+            js_fn!("{}.unknown()").queue(&[JsValue::Null]);
+        });
+        native::take_console();
+
+        // at the end of an event listener, the error of a queued call is logged
+        native::dispatch_event(&button.element, "click");
+        let console = native::take_console();
+        assert_eq!(console.len(), 1);
+        assert_eq!(console[0].0, "error");
+        assert!(console[0]
+            .1
+            .starts_with("NotSupportedError: cannot evaluate "));
+
+        // and otherwise returned
+        js_fn!("{}.unknown()").queue(&[JsValue::Null]);
+        assert_eq!(try_flush().unwrap_err().name, "NotSupportedError");
+        set_batching(false);
+    }
+}
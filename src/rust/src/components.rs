@@ -1,5 +1,249 @@
-use crate::element::Elem;
+use std::fmt::Display;
 
+use crate::{
+    element::Elem,
+    signals::{Memo, Scope, Signal},
+};
+
+// A reusable part of a page. In `html!`, a component is written as a tag with attributes, like
+// `<Badge label="new" count={3} />`, and constructed with `Badge::new(BadgeProps { .. })`
+// where the attributes are the fields of `BadgeProps`, in any order. So a component used in
+// `html!` needs a `new` function taking a props struct, which is named after the component with
+// the suffix `Props` and is in scope where the component is used.
 pub trait Component {
     fn to_elem(&self) -> &Elem;
 }
+
+// Constructs a component in a new scope, which is disposed when the element of the component
+// is cleaned up. The component itself is kept until then too. Contexts provided by the component
+// are only visible to its own descendants, and not to its siblings. `html!` constructs every
+// component with this function.
+pub fn scoped<C: Component + 'static>(f: impl FnOnce() -> C) -> Elem {
+    let scope = Scope::new();
+    let component = scope.run(f);
+    let elem = component.to_elem().clone();
+    elem.on_cleanup(move || {
+        drop(component);
+        scope.dispose();
+    })
+}
+
+// Values that can be placed inside an element by `html!`. Elements are appended,
+// text is added as a text node, and signals, memos and closures are bound with `Elem::text_dyn`.
+pub trait Child {
+    fn append_to(self, parent: Elem) -> Elem;
+}
+
+impl Child for Elem {
+    fn append_to(self, parent: Elem) -> Elem {
+        parent.append(&self)
+    }
+}
+
+impl Child for &Elem {
+    fn append_to(self, parent: Elem) -> Elem {
+        parent.append(self)
+    }
+}
+
+impl Child for Vec<Elem> {
+    fn append_to(self, parent: Elem) -> Elem {
+        self.iter()
+            .fold(parent, |parent, child| parent.append(child))
+    }
+}
+
+impl<T: Child> Child for Option<T> {
+    fn append_to(self, parent: Elem) -> Elem {
+        match self {
+            Some(child) => child.append_to(parent),
+            None => parent,
+        }
+    }
+}
+
+impl Child for &str {
+    fn append_to(self, parent: Elem) -> Elem {
+        parent.text(self)
+    }
+}
+
+impl Child for String {
+    fn append_to(self, parent: Elem) -> Elem {
+        parent.text(&self)
+    }
+}
+
+impl Child for &String {
+    fn append_to(self, parent: Elem) -> Elem {
+        parent.text(self)
+    }
+}
+
+macro_rules! impl_child_display {
+    ($($t:ty),*) => {
+        $(
+            impl Child for $t {
+                fn append_to(self, parent: Elem) -> Elem {
+                    parent.text(&self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_child_display!(bool, char, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl<T: Display + Clone + 'static> Child for Signal<T> {
+    fn append_to(self, parent: Elem) -> Elem {
+        parent.text_dyn(self)
+    }
+}
+
+impl<T: Display + Clone + PartialEq + 'static> Child for Memo<T> {
+    fn append_to(self, parent: Elem) -> Elem {
+        parent.text_dyn(self)
+    }
+}
+
+impl<T: Display, F: Fn() -> T + 'static> Child for F {
+    fn append_to(self, parent: Elem) -> Elem {
+        parent.text_dyn(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        html, native,
+        signals::{derived, on_cleanup, provide_context, use_context},
+    };
+    use std::{cell::Cell, rc::Rc};
+
+    struct Badge {
+        elem: Elem,
+    }
+
+    struct BadgeProps<'a> {
+        label: &'a str,
+        count: i32,
+    }
+
+    impl Badge {
+        fn new(BadgeProps { label, count }: BadgeProps) -> Self {
+            Self {
+                elem: html! { <span class="badge">{label} ": " {count}</span> },
+            }
+        }
+    }
+
+    impl Component for Badge {
+        fn to_elem(&self) -> &Elem {
+            &self.elem
+        }
+    }
+
+    #[test]
+    fn test_html() {
+        let count = Signal::new(1);
+        let double = derived(move || count.get() * 2);
+        let items: Vec<Elem> = (1..=2).map(|i| html! { <li>{i}</li> }).collect();
+        let elem = html! {
+            <div id="app" data-count={count.get()} class:odd={move || count.get() % 2 == 1}>
+                <Badge label="items" count={items.len() as i32} />
+                <Badge count={3} label="more" />
+                <p>"Count: " {count} ", double: " {double} {move || if count.get() > 1 { "!" } else { "" }}</p>
+                <input type="text" disabled prop:value={move || count.get().to_string()} />
+                <ul>{items}</ul>
+                <button on:click={move |_| count.update(|c| *c += 1)}>"+"</button>
+            </div>
+        };
+        assert_eq!(
+            native::outer_html(&elem.element),
+            "<div id=\"app\" data-count=\"1\" class=\"odd\"><span class=\"badge\">items: 2</span>\
+             <span class=\"badge\">more: 3</span>\
+             <p>Count: 1, double: 2</p><input type=\"text\" disabled=\"\"><ul><li>1</li><li>2</li></ul>\
+             <button>+</button></div>"
+        );
+
+        let button = elem
+            .element
+            .call("querySelector", &["button".into()])
+            .to_ref()
+            .unwrap();
+        native::dispatch_event(&button, "click");
+        assert_eq!(
+            native::outer_html(
+                &elem
+                    .element
+                    .call("querySelector", &["p".into()])
+                    .to_ref()
+                    .unwrap()
+            ),
+            "<p>Count: 2, double: 4!</p>"
+        );
+        assert_eq!(elem.element.get("className").to_string(), Ok("".to_owned()));
+    }
+
+    #[derive(Clone)]
+    struct Theme(&'static str);
+
+    struct Themed {
+        elem: Elem,
+        _disposed: Rc<Cell<bool>>,
+    }
+
+    struct ThemedProps {
+        theme: Option<&'static str>,
+        disposed: Rc<Cell<bool>>,
+    }
+
+    impl Themed {
+        fn new(ThemedProps { theme, disposed }: ThemedProps) -> Self {
+            if let Some(theme) = theme {
+                provide_context(Theme(theme));
+            }
+            on_cleanup({
+                let disposed = disposed.clone();
+                move || disposed.set(true)
+            });
+            let theme = use_context::<Theme>().map_or("none", |theme| theme.0);
+            Self {
+                elem: html! { <span>{theme}</span> },
+                _disposed: disposed,
+            }
+        }
+    }
+
+    impl Component for Themed {
+        fn to_elem(&self) -> &Elem {
+            &self.elem
+        }
+    }
+
+    #[test]
+    fn test_component_scope() {
+        // contexts provided by a component are not visible to its siblings
+        let disposed = Rc::new(Cell::new(false));
+        let elem = html! {
+            <div>
+                <Themed theme={Some("dark")} disposed={disposed.clone()} />
+                <Themed theme={None} disposed={Rc::default()} />
+            </div>
+        };
+        assert_eq!(
+            native::outer_html(&elem.element),
+            "<div><span>dark</span><span>none</span></div>"
+        );
+        assert_eq!(use_context::<Theme>().map(|theme| theme.0), None);
+
+        // the component is kept, and its scope is disposed, until it is removed
+        assert!(!disposed.get());
+        assert_eq!(Rc::strong_count(&disposed), 3);
+        elem.remove();
+        assert!(disposed.get());
+        assert_eq!(Rc::strong_count(&disposed), 1);
+    }
+}
@@ -0,0 +1,557 @@
+use serde::{
+    de::{
+        self,
+        value::{Error, MapAccessDeserializer, MapDeserializer, SeqDeserializer},
+        DeserializeOwned, IntoDeserializer, Visitor,
+    },
+    ser::{self, Serialize},
+};
+
+use crate::js::{self, JsValue};
+
+// Converts a Rust value to a `JsValue`. Structs and maps become `JsValue::Dict`,
+// sequences and tuples become `JsValue::List`. Enums are represented like in JSON:
+// unit variants as strings, other variants as `{ variant: value }`.
+pub fn to_js<T: Serialize + ?Sized>(value: &T) -> Result<JsValue, Error> {
+    value.serialize(Serializer)
+}
+
+// Converts a `JsValue` to a Rust value. Referenced arrays and objects are first
+// copied with `js::try_read_deep`.
+pub fn from_js<T: DeserializeOwned>(value: JsValue) -> Result<T, Error> {
+    let value = match value {
+        JsValue::Object(object) | JsValue::Array(object) => {
+            js::try_read_deep(&object).map_err(de::Error::custom)?
+        }
+        value => value,
+    };
+    T::deserialize(value)
+}
+
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = JsValue;
+    type Error = Error;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeVariant<SerializeList>;
+    type SerializeMap = SerializeDict;
+    type SerializeStruct = SerializeDict;
+    type SerializeStructVariant = SerializeVariant<SerializeDict>;
+
+    fn serialize_bool(self, v: bool) -> Result<JsValue, Error> {
+        Ok(JsValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<JsValue, Error> {
+        Ok(JsValue::Number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<JsValue, Error> {
+        Ok(JsValue::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<JsValue, Error> {
+        Ok(JsValue::String(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<JsValue, Error> {
+        Ok(JsValue::Blob(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<JsValue, Error> {
+        Ok(JsValue::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<JsValue, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<JsValue, Error> {
+        Ok(JsValue::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<JsValue, Error> {
+        Ok(JsValue::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<JsValue, Error> {
+        Ok(JsValue::String(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<JsValue, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<JsValue, Error> {
+        Ok(JsValue::Dict(vec![(variant.to_owned(), to_js(value)?)]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeList>, Error> {
+        Ok(SerializeVariant(variant, self.serialize_seq(Some(len))?))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeDict, Error> {
+        Ok(SerializeDict(Vec::with_capacity(len.unwrap_or(0)), None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeDict, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeDict>, Error> {
+        Ok(SerializeVariant(variant, self.serialize_map(Some(len))?))
+    }
+}
+
+struct SerializeList(Vec<JsValue>);
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = JsValue;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.push(to_js(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<JsValue, Error> {
+        Ok(JsValue::List(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = JsValue;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<JsValue, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = JsValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<JsValue, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+// Entries of the object, and the key of the entry whose value is serialized next.
+struct SerializeDict(Vec<(String, JsValue)>, Option<String>);
+
+impl ser::SerializeMap for SerializeDict {
+    type Ok = JsValue;
+    type Error = Error;
+
+    // Keys of JS objects are strings, so numbers and other keys are converted to strings
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let key = match to_js(key)? {
+            JsValue::String(s) => s,
+            JsValue::Number(n) => n.to_string(),
+            JsValue::Bool(b) => b.to_string(),
+            _ => {
+                return Err(ser::Error::custom(
+                    "keys must be strings, numbers or booleans",
+                ))
+            }
+        };
+        self.1 = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.1.take().expect("key is serialized before value");
+        self.0.push((key, to_js(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<JsValue, Error> {
+        Ok(JsValue::Dict(self.0))
+    }
+}
+
+impl ser::SerializeStruct for SerializeDict {
+    type Ok = JsValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.0.push((key.to_owned(), to_js(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<JsValue, Error> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+// Serializes the value of an enum variant, wrapped in `{ variant: value }`.
+struct SerializeVariant<S>(&'static str, S);
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeList> {
+    type Ok = JsValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(&mut self.1, value)
+    }
+
+    fn end(self) -> Result<JsValue, Error> {
+        let value = ser::SerializeSeq::end(self.1)?;
+        Ok(JsValue::Dict(vec![(self.0.to_owned(), value)]))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeDict> {
+    type Ok = JsValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(&mut self.1, key, value)
+    }
+
+    fn end(self) -> Result<JsValue, Error> {
+        let value = ser::SerializeMap::end(self.1)?;
+        Ok(JsValue::Dict(vec![(self.0.to_owned(), value)]))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for JsValue {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            JsValue::Undefined | JsValue::Null => visitor.visit_unit(),
+            JsValue::Bool(b) => visitor.visit_bool(b),
+            // Integers are visited as such, so that they can be deserialized into integer types
+            JsValue::Number(n) if n.fract() == 0.0 && n >= 0.0 && n < u64::MAX as f64 => {
+                visitor.visit_u64(n as u64)
+            }
+            JsValue::Number(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < 0.0 => {
+                visitor.visit_i64(n as i64)
+            }
+            JsValue::Number(n) => visitor.visit_f64(n),
+            JsValue::BigInt(i) => visitor.visit_i64(i),
+            JsValue::String(s) => visitor.visit_string(s),
+            JsValue::Blob(v) => visitor.visit_byte_buf(v),
+            JsValue::List(values) => visitor.visit_seq(SeqDeserializer::new(values.into_iter())),
+            JsValue::Dict(entries) => visitor.visit_map(map_deserializer(entries)),
+            JsValue::TypedArray(array) => {
+                visitor.visit_seq(SeqDeserializer::new(array.into_values().into_iter()))
+            }
+            JsValue::Object(_) | JsValue::Array(_) => Err(de::Error::custom(
+                "cannot deserialize a referenced object, use `js::read_deep` first",
+            )),
+            JsValue::Function(_) => Err(de::Error::custom("cannot deserialize a function")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            JsValue::Undefined | JsValue::Null => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            JsValue::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            JsValue::Dict(entries) if entries.len() == 1 => {
+                visitor.visit_enum(MapAccessDeserializer::new(map_deserializer(entries)))
+            }
+            _ => Err(de::Error::custom(
+                "expected a string or an object with a single entry",
+            )),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl IntoDeserializer<'_, Error> for JsValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+fn map_deserializer<'de>(
+    entries: Vec<(String, JsValue)>,
+) -> MapDeserializer<'de, impl Iterator<Item = (Key, JsValue)>, Error> {
+    MapDeserializer::new(entries.into_iter().map(|(key, value)| (Key(key), value)))
+}
+
+// Keys of JS objects are strings, which are parsed when a number or boolean is expected.
+struct Key(String);
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident),*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self.0.parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(_) => visitor.visit_string(self.0),
+            }
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Key {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.0)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf option unit unit_struct newtype_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl IntoDeserializer<'_, Error> for Key {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::js_fn;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Theme {
+        Light,
+        Dark,
+        Custom { background: String },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        size: (u32, i32),
+        scale: f64,
+        tags: Vec<String>,
+        parent: Option<Box<Config>>,
+        themes: HashMap<u8, Theme>,
+    }
+
+    #[test]
+    fn test_to_js() {
+        let value = to_js(&Theme::Custom {
+            background: "red".to_owned(),
+        })
+        .unwrap();
+        let JsValue::Dict(entries) = value else {
+            panic!("expected dict");
+        };
+        assert_eq!(entries[0].0, "Custom");
+        assert!(matches!(&entries[0].1, JsValue::Dict(fields) if fields[0].0 == "background"));
+
+        let value = to_js(&Theme::Light).unwrap();
+        assert_eq!(value.to_string(), Ok("Light".to_owned()));
+
+        let value = to_js(&vec![Some(1), None]).unwrap();
+        assert!(matches!(&value, JsValue::List(values) if matches!(values[1], JsValue::Null)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let config = Config {
+            name: "main".to_owned(),
+            size: (1920, -1080),
+            scale: 1.5,
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            parent: Some(Box::new(Config {
+                name: "parent".to_owned(),
+                size: (0, 0),
+                scale: 1.0,
+                tags: vec![],
+                parent: None,
+                themes: HashMap::new(),
+            })),
+            themes: HashMap::from([
+                (0, Theme::Dark),
+                (
+                    1,
+                    Theme::Custom {
+                        background: "#fff".to_owned(),
+                    },
+                ),
+            ]),
+        };
+
+        // through the wire format
+        let buffer = js::serialize(&[to_js(&config).unwrap()]);
+        let value = js::deserialize(&buffer).unwrap().pop().unwrap();
+        assert_eq!(from_js::<Config>(value).unwrap(), config);
+
+        // invalid values
+        let value = to_js(&("main", 1)).unwrap();
+        assert!(from_js::<Config>(value).is_err());
+
+        // integers that do not fit in a u64 are floats
+        let max = u64::MAX as f64;
+        assert!(from_js::<u64>(JsValue::Number(max)).is_err());
+        assert_eq!(from_js::<f64>(JsValue::Number(max)).unwrap(), max);
+        assert_eq!(
+            from_js::<u64>(JsValue::Number(2f64.powi(53))).unwrap(),
+            1 << 53
+        );
+    }
+
+    #[test]
+    fn test_from_js_cyclic() {
+        // an array that contains itself cannot be copied
+        let JsValue::Array(array) = js_fn!("return []").invoke(&[]) else {
+            panic!("expected an array");
+        };
+        js_fn!("{}.push({})").invoke(&[(&array).into(), (&array).into()]);
+        let error = from_js::<Vec<Vec<u32>>>(JsValue::Array(array)).unwrap_err();
+        assert_eq!(error.to_string(), "TypeError: cannot copy a cyclic object");
+    }
+}
@@ -1,93 +1,378 @@
 use crate::{
-    callbacks::add_event_listener,
+    callbacks::{
+        add_event_listener, add_event_listener_with_options, EventOptions, ListenerHandle,
+    },
     console_error,
-    js::{self, ObjectRef},
+    events::{EventName, FromEvent},
+    hydration,
+    js::{self, JsValue, ObjectRef},
+    js_fn,
+    signals::{effect, Reactive, Scope},
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashSet},
+    fmt::{self, Debug, Display},
+    mem::ManuallyDrop,
+    rc::{Rc, Weak},
 };
-use std::cell::RefCell;
 
+// NOTE: the event listeners and bindings are shared by all clones of an `Elem`
 #[derive(Debug, Clone)]
 pub struct Elem {
     pub element: ObjectRef,
-    pub callbacks: RefCell<Vec<ObjectRef>>,
+    pub(crate) lifecycle: Rc<Lifecycle>,
+}
+
+// Lifecycle of an element, shared by all clones of an `Elem`. An element keeps the children
+// that were added with `append` and `children`, so that removing it also cleans up its subtree.
+// The children are kept in the order in which they were added, by their `key`.
+// Elements are mounted when they are added to an element in the page. The event listeners and
+// the scope that owns the effects of the reactive bindings are freed when the element is
+// cleaned up, or when the last handle to an element that is not in the page is dropped.
+#[derive(Default)]
+pub(crate) struct Lifecycle {
+    mounted: Cell<bool>,
+    parent: RefCell<Weak<Lifecycle>>,
+    children: RefCell<BTreeMap<u64, Elem>>,
+    next_key: Cell<u64>,
+    // The key of the element in the children of its parent
+    key: Cell<u64>,
+    mount: RefCell<Vec<Box<dyn FnOnce()>>>,
+    cleanup: RefCell<Vec<Box<dyn FnOnce()>>>,
+    listeners: RefCell<Vec<ListenerHandle>>,
+    scope: RefCell<Option<Rc<Scope>>>,
+}
+
+thread_local! {
+    // Elements in the page that have no parent `Elem`, for example the elements of
+    // `Elem::select` that children were added to, or the root of a hydrated page. They are
+    // kept until they are removed, so that their subtree stays alive without handles.
+    // NOTE: they are not dropped with the thread, since the signals may already be destroyed
+    static ROOTS: RefCell<ManuallyDrop<BTreeMap<usize, Rc<Lifecycle>>>> =
+        const { RefCell::new(ManuallyDrop::new(BTreeMap::new())) };
+}
+
+impl Lifecycle {
+    fn mounted() -> Rc<Self> {
+        let lifecycle = Self::default();
+        lifecycle.mounted.set(true);
+        Rc::new(lifecycle)
+    }
+
+    // Adds a child to the element, and mounts it if the element is mounted.
+    pub(crate) fn adopt(self: &Rc<Self>, child: &Elem) {
+        if let Some(parent) = child.lifecycle.parent.take().upgrade() {
+            parent.forget(child);
+        }
+        child.lifecycle.unroot();
+        child.lifecycle.parent.replace(Rc::downgrade(self));
+        let key = self.next_key.replace(self.next_key.get() + 1);
+        child.lifecycle.key.set(key);
+        self.children.borrow_mut().insert(key, child.clone());
+        if self.mounted.get() {
+            mount(child);
+        }
+        self.root();
+    }
+
+    // Runs an effect in the scope of the element, which is created by the first binding.
+    pub(crate) fn bind(self: &Rc<Self>, f: impl FnMut() + 'static) {
+        let scope = self
+            .scope
+            .borrow_mut()
+            .get_or_insert_with(Default::default)
+            .clone();
+        scope.run(|| effect(f));
+        self.root();
+    }
+
+    fn listen(self: &Rc<Self>, handle: ListenerHandle) {
+        self.listeners.borrow_mut().push(handle);
+        self.root();
+    }
+
+    // Keeps an element that is in the page without a parent `Elem`.
+    fn root(self: &Rc<Self>) {
+        if self.mounted.get() && self.parent.borrow().upgrade().is_none() {
+            let key = Rc::as_ptr(self) as usize;
+            ROOTS.with_borrow_mut(|roots| roots.insert(key, self.clone()));
+        }
+    }
+
+    fn unroot(&self) {
+        let key = self as *const Self as usize;
+        // NOTE: the element is dropped outside of the borrow
+        let root = ROOTS.with_borrow_mut(|roots| roots.remove(&key));
+        drop(root);
+    }
+
+    // Removes a child of the element.
+    fn forget(&self, child: &Elem) {
+        let key = child.lifecycle.key.get();
+        let removed = {
+            let mut children = self.children.borrow_mut();
+            match children.get(&key) {
+                Some(c) if Rc::ptr_eq(&c.lifecycle, &child.lifecycle) => children.remove(&key),
+                _ => None,
+            }
+        };
+        drop(removed);
+        let empty = self.children.borrow().is_empty()
+            && self.listeners.borrow().is_empty()
+            && self.scope.borrow().is_none();
+        if empty {
+            self.unroot();
+        }
+    }
+}
+
+impl Debug for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lifecycle")
+            .field("mounted", &self.mounted.get())
+            .field("children", &self.children.borrow().len())
+            .field("listeners", &self.listeners.borrow().len())
+            .finish_non_exhaustive()
+    }
+}
+
+// Runs the mount hooks of the subtree, children first.
+fn mount(elem: &Elem) {
+    if elem.lifecycle.mounted.replace(true) {
+        return;
+    }
+    let children: Vec<Elem> = elem.lifecycle.children.borrow().values().cloned().collect();
+    children.iter().for_each(mount);
+    for hook in elem.lifecycle.mount.take() {
+        hook();
+    }
+}
+
+// Runs the cleanup hooks and removes the event listeners of the subtree, children first.
+fn cleanup(elem: &Elem) {
+    let children = elem.lifecycle.children.take();
+    children.values().for_each(cleanup);
+    elem.lifecycle.listeners.take();
+    elem.lifecycle.scope.take();
+    elem.lifecycle.unroot();
+    elem.lifecycle.mounted.set(false);
+    elem.lifecycle.mount.take();
+    for hook in elem.lifecycle.cleanup.take().into_iter().rev() {
+        hook();
+    }
 }
 
 impl Elem {
     pub fn new(tag: &str) -> Self {
-        let element = match js::create_element(tag).to_ref() {
+        let element = match hydration::create_element(tag).to_ref() {
             Ok(r) => r,
             Err(_) => {
                 console_error!("Failed to created element with tag '{}'", tag,);
                 panic!();
             }
         };
-        Self {
-            element,
-            callbacks: RefCell::new(vec![]),
-        }
+        // While hydrating, claimed elements are already in the page
+        let lifecycle = if hydration::is_claimed(&element) {
+            Lifecycle::mounted()
+        } else {
+            Default::default()
+        };
+        Self { element, lifecycle }
     }
 
     pub fn select(query: &str) -> Result<Self, &'static str> {
         match js::query_selector(query).to_ref() {
-            Ok(r) => Ok(Self::from(r)),
+            Ok(r) => Ok(Self {
+                element: r,
+                lifecycle: Lifecycle::mounted(),
+            }),
             Err(_) => Err("query did not match any element"),
         }
     }
 
     pub fn append(self, child: &Elem) -> Self {
-        js::invoke(
-            "{}.append({})",
-            &[self.element.clone().into(), child.element.clone().into()],
-        );
+        // While hydrating, server-rendered children are already in place
+        if !hydration::is_claimed(&self.element) || !hydration::is_claimed(&child.element) {
+            js_fn!("{}.append({})")
+                .queue(&[self.element.clone().into(), child.element.clone().into()]);
+        }
+        self.lifecycle.adopt(child);
         self
     }
 
+    // Removes the element from the page, and cleans up the element and its descendants.
     pub fn remove(self) {
-        js::invoke("{}.remove()", &[self.element.into()]);
+        js_fn!("{}.remove()").queue(&[self.element.clone().into()]);
+
+        if let Some(parent) = self.lifecycle.parent.take().upgrade() {
+            parent.forget(&self);
+        }
+        cleanup(&self);
+    }
+
+    // Runs a function when the element is added to the page, or right away if it already is.
+    pub fn on_mount(self, f: impl FnOnce() + 'static) -> Self {
+        if self.lifecycle.mounted.get() {
+            f();
+        } else {
+            self.lifecycle.mount.borrow_mut().push(Box::new(f));
+        }
+        self
+    }
+
+    // Runs a function when the element, or one of its ancestors, is removed with `remove`
+    // or replaced with `children`. For example, to stop a timer or to dispose a `Scope`.
+    pub fn on_cleanup(self, f: impl FnOnce() + 'static) -> Self {
+        self.lifecycle.cleanup.borrow_mut().push(Box::new(f));
+        self
     }
 
     pub fn attr(self, name: &str, value: &str) -> Self {
-        js::invoke(
-            "{}.setAttribute({},{})",
-            &[self.element.clone().into(), name.into(), value.into()],
-        );
+        js_fn!("{}.setAttribute({},{})").queue(&[
+            self.element.clone().into(),
+            name.into(),
+            value.into(),
+        ]);
         self
     }
 
     pub fn class(self, class: &str) -> Self {
-        js::invoke(
-            "{}.classList.add(...{}.split(' '))",
-            &[self.element.clone().into(), class.into()],
-        );
+        js_fn!("{}.classList.add(...{}.split(' '))")
+            .queue(&[self.element.clone().into(), class.into()]);
         self
     }
 
     pub fn children(self, children: &[&Self]) -> Self {
-        js::invoke(
-            "{}.innerHTML = {}",
-            &[self.element.clone().into(), "".into()],
-        );
+        if hydration::is_claimed(&self.element) {
+            for child in children {
+                self.clone().append(child);
+            }
+            return self;
+        }
+
+        // Clean up the previous children that are replaced
+        let kept: HashSet<*const Lifecycle> =
+            children.iter().map(|c| Rc::as_ptr(&c.lifecycle)).collect();
+        let previous = self.lifecycle.children.take();
+        for child in previous.values() {
+            if !kept.contains(&Rc::as_ptr(&child.lifecycle)) {
+                child.lifecycle.parent.take();
+                cleanup(child);
+            }
+        }
+
+        js_fn!("{}.innerHTML = {}").queue(&[self.element.clone().into(), "".into()]);
         for child in children {
-            js::invoke(
-                "{}.appendChild({})",
-                &[self.element.clone().into(), child.element.clone().into()],
-            );
+            js_fn!("{}.appendChild({})")
+                .queue(&[self.element.clone().into(), child.element.clone().into()]);
+            self.lifecycle.adopt(child);
         }
         self
     }
 
     pub fn on(self, event: &str, callback: impl FnMut(ObjectRef) + 'static) -> Self {
-        add_event_listener(&self.element, event, callback);
+        let handle = add_event_listener(&self.element, event, callback);
+        self.lifecycle.listen(handle);
+        self
+    }
+
+    pub fn on_with(
+        self,
+        event: &str,
+        options: EventOptions,
+        callback: impl FnMut(ObjectRef) + 'static,
+    ) -> Self {
+        let handle = add_event_listener_with_options(&self.element, event, &options, callback);
+        self.lifecycle.listen(handle);
         self
     }
 
+    // Adds an event listener whose callback receives the type of event of its name, for
+    // example `elem.on_typed(KEYDOWN, |e| { ... })` where `e` is a `KeyboardEvent`.
+    pub fn on_typed<E: FromEvent>(
+        self,
+        event: EventName<E>,
+        mut callback: impl FnMut(E) + 'static,
+    ) -> Self {
+        self.on(event.name(), move |e| callback(E::from_event(e)))
+    }
+
     pub fn text(self, text: &str) -> Self {
-        let text = js::invoke("return document.createTextNode({})", &[text.into()])
-            .to_ref()
-            .unwrap();
-        js::invoke(
-            "{}.appendChild({})",
-            &[self.element.clone().into(), text.into()],
-        );
+        if hydration::is_claimed(&self.element) {
+            hydration::claim_text(&self.element, text);
+            return self;
+        }
+        js_fn!("{}.append({})").queue(&[self.element.clone().into(), text.into()]);
+        self
+    }
+}
+
+// Reactive bindings. Each binding runs in an effect that only updates its own
+// text node, attribute, class, style or property when the value changes.
+// The effects stop when the element is removed.
+impl Elem {
+    pub fn text_dyn<T: Display>(self, text: impl Reactive<T> + 'static) -> Self {
+        let node = hydration::create_text_node(&self.element);
+        self.lifecycle.bind(move || {
+            let value = text.get().to_string();
+            js_fn!("{}.nodeValue = {}").queue(&[node.clone().into(), value.into()]);
+        });
+        self
+    }
+
+    pub fn attr_dyn<T: Display>(self, name: &str, value: impl Reactive<T> + 'static) -> Self {
+        let element = self.element.clone();
+        let name = name.to_owned();
+        self.lifecycle.bind(move || {
+            let value = value.get().to_string();
+            js_fn!("{}.setAttribute({},{})").queue(&[
+                element.clone().into(),
+                name.clone().into(),
+                value.into(),
+            ]);
+        });
+        self
+    }
+
+    pub fn class_toggle(self, class: &str, enabled: impl Reactive<bool> + 'static) -> Self {
+        let element = self.element.clone();
+        let class = class.to_owned();
+        self.lifecycle.bind(move || {
+            js_fn!("{}.classList.toggle({},{})").queue(&[
+                element.clone().into(),
+                class.clone().into(),
+                enabled.get().into(),
+            ]);
+        });
+        self
+    }
+
+    pub fn style_dyn<T: Display>(self, property: &str, value: impl Reactive<T> + 'static) -> Self {
+        let element = self.element.clone();
+        let property = property.to_owned();
+        self.lifecycle.bind(move || {
+            let value = value.get().to_string();
+            js_fn!("{}.style.setProperty({},{})").queue(&[
+                element.clone().into(),
+                property.clone().into(),
+                value.into(),
+            ]);
+        });
+        self
+    }
+
+    // Binds a property of the element, for example the `value` of an `<input>`.
+    pub fn prop_dyn<T: Into<JsValue>>(self, name: &str, value: impl Reactive<T> + 'static) -> Self {
+        let element = self.element.clone();
+        let name = name.to_owned();
+        self.lifecycle.bind(move || {
+            js_fn!("{}[{}] = {}").queue(&[
+                element.clone().into(),
+                name.clone().into(),
+                value.get().into(),
+            ]);
+        });
         self
     }
 }
@@ -96,7 +381,7 @@ impl From<&ObjectRef> for Elem {
     fn from(value: &ObjectRef) -> Self {
         Self {
             element: value.to_owned(),
-            callbacks: RefCell::new(vec![]),
+            lifecycle: Default::default(),
         }
     }
 }
@@ -105,7 +390,126 @@ impl From<ObjectRef> for Elem {
     fn from(value: ObjectRef) -> Self {
         Self {
             element: value,
-            callbacks: RefCell::new(vec![]),
+            lifecycle: Default::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{native, signals::Signal};
+
+    #[test]
+    fn test_lifecycle() {
+        let log: Rc<RefCell<Vec<String>>> = Default::default();
+        let hooks = |name: &'static str| {
+            let (mount, cleanup) = (log.clone(), log.clone());
+            Elem::new("div")
+                .on_mount(move || mount.borrow_mut().push(format!("mount {}", name)))
+                .on_cleanup(move || cleanup.borrow_mut().push(format!("cleanup {}", name)))
+        };
+
+        // elements are mounted when they are added to the page
+        let outer = hooks("outer").append(&hooks("inner"));
+        assert!(log.borrow().is_empty());
+        let body = Elem::select("body").unwrap();
+        body.clone().append(&outer);
+        assert_eq!(log.take(), vec!["mount inner", "mount outer"]);
+
+        // mount hooks of mounted elements run right away
+        let clicks = Rc::new(Cell::new(0));
+        let elem = hooks("child").on("click", {
+            let clicks = clicks.clone();
+            move |_| clicks.set(clicks.get() + 1)
+        });
+        let outer = outer.append(&elem);
+        assert_eq!(log.take(), vec!["mount child"]);
+
+        // replacing the children cleans up the replaced ones
+        let outer = outer.children(&[&elem]);
+        assert_eq!(log.take(), vec!["cleanup inner"]);
+
+        // removing an element cleans up its subtree and removes the listeners
+        outer.remove();
+        assert_eq!(log.take(), vec!["cleanup child", "cleanup outer"]);
+        native::dispatch_event(&elem.element, "click");
+        assert_eq!(clicks.get(), 0);
+        assert_eq!(native::inner_html(&body.element), "");
+    }
+
+    #[test]
+    fn test_bindings_stop_on_removal() {
+        let count = Signal::new(0);
+        let runs = Rc::new(Cell::new(0));
+        let text = {
+            let runs = runs.clone();
+            move || {
+                runs.set(runs.get() + 1);
+                count.get()
+            }
+        };
+        let renders = Rc::new(Cell::new(0));
+        let render = {
+            let renders = renders.clone();
+            move |i: &i32| {
+                renders.set(renders.get() + 1);
+                Elem::new("li").text(&i.to_string())
+            }
+        };
+        let list = Elem::new("ul").each(move || vec![count.get()], |i| *i, render);
+        let elem = Elem::new("p").text_dyn(text).append(&list);
+        Elem::select("body").unwrap().append(&elem);
+        count.set(1);
+        assert_eq!((runs.get(), renders.get()), (2, 2));
+
+        // the effects of removed elements no longer run
+        elem.remove();
+        count.set(2);
+        assert_eq!((runs.get(), renders.get()), (2, 2));
+    }
+
+    #[test]
+    fn test_drop_listeners() {
+        let clicks = Rc::new(Cell::new(0));
+        let listener = |clicks: &Rc<Cell<i32>>| {
+            let clicks = clicks.clone();
+            move |_| clicks.set(clicks.get() + 1)
+        };
+
+        // the listeners of an element in the page are kept when its handles are dropped
+        let elem = Elem::new("button").on("click", listener(&clicks));
+        let object = elem.element.clone();
+        Elem::select("body").unwrap().append(&elem);
+        drop(elem);
+        native::dispatch_event(&object, "click");
+        assert_eq!(clicks.get(), 1);
+
+        // the listeners of an element that was never added to the page are freed
+        let clicks = Rc::new(Cell::new(0));
+        let elem = Elem::new("button").on("click", listener(&clicks));
+        let object = elem.element.clone();
+        drop(elem);
+        assert_eq!(Rc::strong_count(&clicks), 1);
+        native::dispatch_event(&object, "click");
+        assert_eq!(clicks.get(), 0);
+
+        // the listeners of an element without handles are freed when it is replaced
+        let clicks = Rc::new(Cell::new(0));
+        let list = Elem::new("div").append(&Elem::new("button").on("click", listener(&clicks)));
+        Elem::select("body").unwrap().append(&list);
+        assert_eq!(Rc::strong_count(&clicks), 2);
+        list.clone().children(&[]);
+        assert_eq!(Rc::strong_count(&clicks), 1);
+
+        // and when the element is removed
+        let clicks = Rc::new(Cell::new(0));
+        let elem = Elem::new("button").on("click", listener(&clicks));
+        Elem::select("body").unwrap().append(&elem);
+        elem.remove();
+        assert_eq!(Rc::strong_count(&clicks), 1);
+        // the elements of `select` are only kept while they have children
+        assert_eq!(ROOTS.with_borrow(|roots| roots.len()), 2);
+    }
+}
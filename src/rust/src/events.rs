@@ -0,0 +1,522 @@
+use std::{marker::PhantomData, ops::Deref};
+
+use crate::js::ObjectRef;
+
+// Typed wrappers around the event object that is passed to an event listener.
+pub trait FromEvent {
+    fn from_event(event: ObjectRef) -> Self;
+}
+
+// The name of an event, and the type of the event that its listeners receive, for example
+// `elem.on_typed(KEYDOWN, |e| ...)` where `e` is a `KeyboardEvent`. Other events can be
+// named with `EventName::new`.
+#[derive(Debug)]
+pub struct EventName<E> {
+    name: &'static str,
+    event: PhantomData<fn() -> E>,
+}
+
+impl<E: FromEvent> EventName<E> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            event: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<E> Clone for EventName<E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<E> Copy for EventName<E> {}
+
+pub const CHANGE: EventName<Event> = EventName::new("change");
+pub const SCROLL: EventName<Event> = EventName::new("scroll");
+pub const CLICK: EventName<MouseEvent> = EventName::new("click");
+pub const DBLCLICK: EventName<MouseEvent> = EventName::new("dblclick");
+pub const CONTEXTMENU: EventName<MouseEvent> = EventName::new("contextmenu");
+pub const MOUSEDOWN: EventName<MouseEvent> = EventName::new("mousedown");
+pub const MOUSEUP: EventName<MouseEvent> = EventName::new("mouseup");
+pub const MOUSEMOVE: EventName<MouseEvent> = EventName::new("mousemove");
+pub const MOUSEENTER: EventName<MouseEvent> = EventName::new("mouseenter");
+pub const MOUSELEAVE: EventName<MouseEvent> = EventName::new("mouseleave");
+pub const POINTERDOWN: EventName<PointerEvent> = EventName::new("pointerdown");
+pub const POINTERUP: EventName<PointerEvent> = EventName::new("pointerup");
+pub const POINTERMOVE: EventName<PointerEvent> = EventName::new("pointermove");
+pub const POINTERCANCEL: EventName<PointerEvent> = EventName::new("pointercancel");
+pub const KEYDOWN: EventName<KeyboardEvent> = EventName::new("keydown");
+pub const KEYUP: EventName<KeyboardEvent> = EventName::new("keyup");
+pub const INPUT: EventName<InputEvent> = EventName::new("input");
+pub const BEFOREINPUT: EventName<InputEvent> = EventName::new("beforeinput");
+pub const FOCUS: EventName<FocusEvent> = EventName::new("focus");
+pub const BLUR: EventName<FocusEvent> = EventName::new("blur");
+pub const FOCUSIN: EventName<FocusEvent> = EventName::new("focusin");
+pub const FOCUSOUT: EventName<FocusEvent> = EventName::new("focusout");
+pub const SUBMIT: EventName<SubmitEvent> = EventName::new("submit");
+
+fn get_bool(object: &ObjectRef, name: &str) -> bool {
+    object.get(name).to_bool().unwrap_or(false)
+}
+
+fn get_num(object: &ObjectRef, name: &str) -> f64 {
+    object.get(name).to_num().unwrap_or(0.0)
+}
+
+fn get_string(object: &ObjectRef, name: &str) -> String {
+    object.get(name).to_string().unwrap_or_default()
+}
+
+// https://developer.mozilla.org/en-US/docs/Web/API/Event
+#[derive(Debug, Clone)]
+pub struct Event(ObjectRef);
+
+impl Event {
+    pub fn object(&self) -> &ObjectRef {
+        &self.0
+    }
+
+    pub fn prevent_default(&self) {
+        self.0.call("preventDefault", &[]);
+    }
+
+    pub fn stop_propagation(&self) {
+        self.0.call("stopPropagation", &[]);
+    }
+
+    pub fn stop_immediate_propagation(&self) {
+        self.0.call("stopImmediatePropagation", &[]);
+    }
+
+    pub fn default_prevented(&self) -> bool {
+        get_bool(&self.0, "defaultPrevented")
+    }
+
+    pub fn event_type(&self) -> String {
+        get_string(&self.0, "type")
+    }
+
+    pub fn target(&self) -> Option<ObjectRef> {
+        self.0.get("target").to_ref().ok()
+    }
+
+    pub fn current_target(&self) -> Option<ObjectRef> {
+        self.0.get("currentTarget").to_ref().ok()
+    }
+
+    // Value of the target element, for example of an `<input>` or a `<select>`.
+    pub fn target_value(&self) -> Option<String> {
+        self.0.get_path("target.value").to_string().ok()
+    }
+
+    pub fn target_checked(&self) -> Option<bool> {
+        self.0.get_path("target.checked").to_bool().ok()
+    }
+}
+
+impl FromEvent for Event {
+    fn from_event(event: ObjectRef) -> Self {
+        Self(event)
+    }
+}
+
+impl FromEvent for ObjectRef {
+    fn from_event(event: ObjectRef) -> Self {
+        event
+    }
+}
+
+// https://developer.mozilla.org/en-US/docs/Web/API/MouseEvent
+#[derive(Debug, Clone)]
+pub struct MouseEvent(Event);
+
+impl MouseEvent {
+    pub fn client_x(&self) -> f64 {
+        get_num(&self.0 .0, "clientX")
+    }
+
+    pub fn client_y(&self) -> f64 {
+        get_num(&self.0 .0, "clientY")
+    }
+
+    pub fn page_x(&self) -> f64 {
+        get_num(&self.0 .0, "pageX")
+    }
+
+    pub fn page_y(&self) -> f64 {
+        get_num(&self.0 .0, "pageY")
+    }
+
+    pub fn offset_x(&self) -> f64 {
+        get_num(&self.0 .0, "offsetX")
+    }
+
+    pub fn offset_y(&self) -> f64 {
+        get_num(&self.0 .0, "offsetY")
+    }
+
+    pub fn button(&self) -> i16 {
+        get_num(&self.0 .0, "button") as i16
+    }
+
+    pub fn buttons(&self) -> u16 {
+        get_num(&self.0 .0, "buttons") as u16
+    }
+
+    pub fn alt_key(&self) -> bool {
+        get_bool(&self.0 .0, "altKey")
+    }
+
+    pub fn ctrl_key(&self) -> bool {
+        get_bool(&self.0 .0, "ctrlKey")
+    }
+
+    pub fn shift_key(&self) -> bool {
+        get_bool(&self.0 .0, "shiftKey")
+    }
+
+    pub fn meta_key(&self) -> bool {
+        get_bool(&self.0 .0, "metaKey")
+    }
+}
+
+impl Deref for MouseEvent {
+    type Target = Event;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromEvent for MouseEvent {
+    fn from_event(event: ObjectRef) -> Self {
+        Self(Event(event))
+    }
+}
+
+// https://developer.mozilla.org/en-US/docs/Web/API/PointerEvent
+#[derive(Debug, Clone)]
+pub struct PointerEvent(MouseEvent);
+
+impl PointerEvent {
+    pub fn pointer_id(&self) -> i32 {
+        get_num(self.object(), "pointerId") as i32
+    }
+
+    pub fn pointer_type(&self) -> String {
+        get_string(self.object(), "pointerType")
+    }
+
+    pub fn pressure(&self) -> f64 {
+        get_num(self.object(), "pressure")
+    }
+
+    pub fn width(&self) -> f64 {
+        get_num(self.object(), "width")
+    }
+
+    pub fn height(&self) -> f64 {
+        get_num(self.object(), "height")
+    }
+
+    pub fn is_primary(&self) -> bool {
+        get_bool(self.object(), "isPrimary")
+    }
+}
+
+impl Deref for PointerEvent {
+    type Target = MouseEvent;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromEvent for PointerEvent {
+    fn from_event(event: ObjectRef) -> Self {
+        Self(MouseEvent::from_event(event))
+    }
+}
+
+// https://developer.mozilla.org/en-US/docs/Web/API/KeyboardEvent
+#[derive(Debug, Clone)]
+pub struct KeyboardEvent(Event);
+
+impl KeyboardEvent {
+    pub fn key(&self) -> String {
+        get_string(&self.0 .0, "key")
+    }
+
+    pub fn code(&self) -> String {
+        get_string(&self.0 .0, "code")
+    }
+
+    pub fn repeat(&self) -> bool {
+        get_bool(&self.0 .0, "repeat")
+    }
+
+    pub fn is_composing(&self) -> bool {
+        get_bool(&self.0 .0, "isComposing")
+    }
+
+    pub fn alt_key(&self) -> bool {
+        get_bool(&self.0 .0, "altKey")
+    }
+
+    pub fn ctrl_key(&self) -> bool {
+        get_bool(&self.0 .0, "ctrlKey")
+    }
+
+    pub fn shift_key(&self) -> bool {
+        get_bool(&self.0 .0, "shiftKey")
+    }
+
+    pub fn meta_key(&self) -> bool {
+        get_bool(&self.0 .0, "metaKey")
+    }
+}
+
+impl Deref for KeyboardEvent {
+    type Target = Event;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromEvent for KeyboardEvent {
+    fn from_event(event: ObjectRef) -> Self {
+        Self(Event(event))
+    }
+}
+
+// https://developer.mozilla.org/en-US/docs/Web/API/InputEvent
+#[derive(Debug, Clone)]
+pub struct InputEvent(Event);
+
+impl InputEvent {
+    pub fn data(&self) -> Option<String> {
+        self.0 .0.get("data").to_string().ok()
+    }
+
+    pub fn input_type(&self) -> String {
+        get_string(&self.0 .0, "inputType")
+    }
+
+    pub fn is_composing(&self) -> bool {
+        get_bool(&self.0 .0, "isComposing")
+    }
+}
+
+impl Deref for InputEvent {
+    type Target = Event;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromEvent for InputEvent {
+    fn from_event(event: ObjectRef) -> Self {
+        Self(Event(event))
+    }
+}
+
+// https://developer.mozilla.org/en-US/docs/Web/API/FocusEvent
+#[derive(Debug, Clone)]
+pub struct FocusEvent(Event);
+
+impl FocusEvent {
+    pub fn related_target(&self) -> Option<ObjectRef> {
+        self.0 .0.get("relatedTarget").to_ref().ok()
+    }
+}
+
+impl Deref for FocusEvent {
+    type Target = Event;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromEvent for FocusEvent {
+    fn from_event(event: ObjectRef) -> Self {
+        Self(Event(event))
+    }
+}
+
+// https://developer.mozilla.org/en-US/docs/Web/API/SubmitEvent
+#[derive(Debug, Clone)]
+pub struct SubmitEvent(Event);
+
+impl SubmitEvent {
+    pub fn submitter(&self) -> Option<ObjectRef> {
+        self.0 .0.get("submitter").to_ref().ok()
+    }
+}
+
+impl Deref for SubmitEvent {
+    type Target = Event;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromEvent for SubmitEvent {
+    fn from_event(event: ObjectRef) -> Self {
+        Self(Event(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{element::Elem, native};
+    use std::{cell::RefCell, rc::Rc};
+
+    type Logs = Rc<RefCell<Vec<String>>>;
+
+    // Logs a line for every event that reaches the listener.
+    fn log<E: FromEvent>(logs: &Logs, f: impl Fn(E) -> String + 'static) -> impl FnMut(E) {
+        let logs = logs.clone();
+        move |event| logs.borrow_mut().push(f(event))
+    }
+
+    #[test]
+    fn test_keyboard_event() {
+        let logs = Logs::default();
+        let input = Elem::new("input").on_typed(
+            KEYDOWN,
+            log(&logs, |e: KeyboardEvent| {
+                format!(
+                    "{} {} {} {}",
+                    e.event_type(),
+                    e.key(),
+                    e.code(),
+                    e.ctrl_key()
+                )
+            }),
+        );
+
+        native::dispatch_event_with(
+            &input.element,
+            "keydown",
+            &[
+                ("key", "a".into()),
+                ("code", "KeyA".into()),
+                ("ctrlKey", true.into()),
+            ],
+        );
+        // missing properties have default values
+        native::dispatch_event(&input.element, "keydown");
+        assert_eq!(
+            *logs.borrow(),
+            vec!["keydown a KeyA true", "keydown   false"]
+        );
+    }
+
+    #[test]
+    fn test_mouse_event() {
+        let logs = Logs::default();
+        let label = Elem::new("span").text("ok");
+        let _button = Elem::new("button").append(&label).on_typed(
+            CLICK,
+            log(&logs, |e: MouseEvent| {
+                let target = e.target().map(|t| native::outer_html(&t));
+                let current = e.current_target().map(|t| native::outer_html(&t));
+                format!(
+                    "{} {} {} {} {:?} {:?}",
+                    e.client_x(),
+                    e.client_y(),
+                    e.button(),
+                    e.shift_key(),
+                    target,
+                    current
+                )
+            }),
+        );
+
+        // the target is the clicked element, and the current target the element of the listener
+        native::dispatch_event_with(
+            &label.element,
+            "click",
+            &[("clientX", 10.into()), ("clientY", 20.5.into())],
+        );
+        assert_eq!(
+            *logs.borrow(),
+            vec!["10 20.5 0 false Some(\"<span>ok</span>\") Some(\"<button><span>ok</span></button>\")"]
+        );
+    }
+
+    #[test]
+    fn test_target_value() {
+        let logs = Logs::default();
+        let format = |e: Event| format!("{:?} {:?}", e.target_value(), e.target_checked());
+        let input = Elem::new("input")
+            .attr("type", "checkbox")
+            .on_typed(CHANGE, log(&logs, format));
+        let div = Elem::new("div").on_typed(CHANGE, log(&logs, format));
+
+        native::dispatch_event(&input.element, "change");
+        input.element.set("value", "on");
+        input.element.set("checked", true);
+        native::dispatch_event(&input.element, "change");
+        // elements without a value
+        native::dispatch_event(&div.element, "change");
+        assert_eq!(
+            *logs.borrow(),
+            vec![
+                "Some(\"\") Some(false)",
+                "Some(\"on\") Some(true)",
+                "None None"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_propagation() {
+        let logs = Logs::default();
+        let prevented = |name: &'static str| {
+            log(&logs, move |e: MouseEvent| {
+                format!("{}:{}", name, e.default_prevented())
+            })
+        };
+
+        // listeners can cancel the event, which still bubbles up
+        let button = Elem::new("button")
+            .on_typed(CLICK, |e| e.prevent_default())
+            .on_typed(CLICK, prevented("button"));
+        let form = Elem::new("form")
+            .on_typed(CLICK, prevented("form"))
+            .append(&button);
+        assert!(!native::dispatch_event(&button.element, "click"));
+        assert_eq!(*logs.borrow(), vec!["button:true", "form:true"]);
+        assert!(native::dispatch_event(&form.element, "click"));
+
+        // stopping the propagation still runs the other listeners of the element
+        logs.borrow_mut().clear();
+        let button = button
+            .on_typed(CLICK, |e| e.stop_propagation())
+            .on_typed(CLICK, prevented("stopped"));
+        native::dispatch_event(&button.element, "click");
+        assert_eq!(*logs.borrow(), vec!["button:true", "stopped:true"]);
+
+        // unless it is stopped immediately
+        logs.borrow_mut().clear();
+        let div = Elem::new("div")
+            .on_typed(CLICK, |e| e.stop_immediate_propagation())
+            .on_typed(CLICK, prevented("div"));
+        form.append(&div);
+        native::dispatch_event(&div.element, "click");
+        assert!(logs.borrow().is_empty());
+    }
+}
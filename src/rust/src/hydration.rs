@@ -0,0 +1,423 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+};
+
+use crate::{
+    array::Array,
+    console_warn,
+    element::Elem,
+    js::{self, JsValue, ObjectRef},
+    js_fn,
+};
+
+// Hydration attaches `Elem` handles, event listeners and signal bindings to server-rendered
+// nodes, instead of creating new ones. The renderer numbers the nodes in the order in which they
+// are created: elements get a `data-hk` attribute, and the nodes of dynamic text and lists get a
+// `<!--hk:N-->` comment. While hydrating, running the same component code claims the nodes with
+// the same numbers. Nodes that cannot be found are created instead, and reported with `console_warn!`.
+enum Mode {
+    Render,
+    Hydrate(Index),
+}
+
+// The server-rendered elements and comments by key, found in one walk over the page.
+#[derive(Clone)]
+struct Index {
+    elements: Array,
+    comments: Array,
+}
+
+struct State {
+    mode: Mode,
+    next_key: u32,
+    // Claimed nodes by id, whose children are already in place. The nodes are kept
+    // alive, so that their ids cannot be reused by other objects while hydrating.
+    claimed: HashMap<u32, ObjectRef>,
+    // Static text of the claimed elements by id, which is compared with the server-rendered
+    // text when hydration finishes.
+    texts: BTreeMap<u32, String>,
+}
+
+thread_local! {
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+// Installs a state, and restores the previous one when dropped, also if the component panics.
+struct Guard {
+    previous: Option<Option<State>>,
+}
+
+impl Guard {
+    fn install(mode: Mode) -> Self {
+        let state = State {
+            mode,
+            next_key: 0,
+            claimed: HashMap::new(),
+            texts: BTreeMap::new(),
+        };
+        Self {
+            previous: Some(STATE.replace(Some(state))),
+        }
+    }
+
+    // Restores the previous state, and returns the installed one.
+    fn finish(mut self) -> Option<State> {
+        STATE.replace(self.previous.take()?)
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            // NOTE: the claimed nodes are dropped outside of the borrow
+            let state = STATE.replace(previous);
+            drop(state);
+        }
+    }
+}
+
+// Runs the component on the children of `root`, which were rendered by
+// `ssr::render_to_string_hydratable` with the same component.
+pub fn hydrate(root: &ObjectRef, component: impl FnOnce() -> Elem) -> Elem {
+    let guard = Guard::install(Mode::Hydrate(Index::new(root)));
+    let elem = component();
+    let state = guard.finish().unwrap();
+
+    let Mode::Hydrate(index) = &state.mode else {
+        unreachable!();
+    };
+    if index.len() > state.next_key as usize {
+        console_warn!(
+            "hydration mismatch: the server rendered more than {} nodes",
+            state.next_key
+        );
+    }
+    for (id, text) in &state.texts {
+        let found = js_fn!(STATIC_TEXT)
+            .invoke(&[state.claimed[id].clone().into()])
+            .to_string()
+            .unwrap_or_default();
+        if found != *text {
+            console_warn!(
+                "hydration mismatch: expected text {:?}, found {:?}",
+                text,
+                found
+            );
+        }
+    }
+    elem
+}
+
+// Runs `f` while numbering the created nodes, so that they can be hydrated later on.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn render<R>(f: impl FnOnce() -> R) -> R {
+    let guard = Guard::install(Mode::Render);
+    let result = f();
+    drop(guard);
+    result
+}
+
+// The key of the next node, and the index of the page if hydrating.
+fn next_key() -> Option<(u32, Option<Index>)> {
+    STATE.with_borrow_mut(|state| {
+        let state = state.as_mut()?;
+        let key = state.next_key;
+        state.next_key += 1;
+        match &state.mode {
+            Mode::Render => Some((key, None)),
+            Mode::Hydrate(index) => Some((key, Some(index.clone()))),
+        }
+    })
+}
+
+fn claim(node: &ObjectRef) {
+    STATE.with_borrow_mut(|state| {
+        if let Some(state) = state {
+            state.claimed.insert(node.id(), node.clone());
+        }
+    });
+}
+
+// Whether the node was rendered on the server, in which case its children are already in place.
+pub(crate) fn is_claimed(node: &ObjectRef) -> bool {
+    STATE.with_borrow(|state| {
+        state
+            .as_ref()
+            .is_some_and(|state| state.claimed.contains_key(&node.id()))
+    })
+}
+
+// Records static text added to a claimed element, which is not added again.
+pub(crate) fn claim_text(node: &ObjectRef, text: &str) {
+    STATE.with_borrow_mut(|state| {
+        if let Some(state) = state {
+            state.texts.entry(node.id()).or_default().push_str(text);
+        }
+    });
+}
+
+// The snippet that returns the arrays of elements and comments of the page by key.
+pub(crate) const INDEX_NODES: &str = "const e = [], c = [], w = document.createTreeWalker({}, 129); while (w.nextNode()) { const n = w.currentNode; if (n.nodeType === 1) { if (n.hasAttribute('data-hk')) e[n.getAttribute('data-hk')] = n } else if (n.data.startsWith('hk:')) c[n.data.slice(3)] = n } return [e, c]";
+
+// The snippet that returns the static text of an element, that is its text nodes except the
+// ones of dynamic text, which follow a `<!--hk:N-->` comment.
+pub(crate) const STATIC_TEXT: &str = "let t = ''; for (const n of {}.childNodes) { const p = n.previousSibling; if (n.nodeType === 3 && !(p && p.nodeType === 8 && p.data.startsWith('hk:'))) t += n.data } return t";
+
+impl Index {
+    fn new(root: &ObjectRef) -> Self {
+        let index = js_fn!(INDEX_NODES)
+            .invoke(&[root.into()])
+            .to_array()
+            .unwrap();
+        Self {
+            elements: index.get(0).to_array().unwrap(),
+            comments: index.get(1).to_array().unwrap(),
+        }
+    }
+
+    fn element(&self, key: u32) -> Option<ObjectRef> {
+        self.elements.get(key as usize).to_ref().ok()
+    }
+
+    fn comment(&self, key: u32) -> Option<ObjectRef> {
+        self.comments.get(key as usize).to_ref().ok()
+    }
+
+    // One more than the largest key of the page.
+    fn len(&self) -> usize {
+        self.elements.len().max(self.comments.len())
+    }
+}
+
+// Creates an element, or claims the server-rendered element while hydrating.
+pub(crate) fn create_element(tag: &str) -> JsValue {
+    let Some((key, index)) = next_key() else {
+        return js::create_element(tag);
+    };
+    let Some(index) = index else {
+        let element = js::create_element(tag).to_ref().unwrap();
+        js_fn!("{}.setAttribute({},{})").queue(&[
+            element.clone().into(),
+            "data-hk".into(),
+            key.to_string().into(),
+        ]);
+        return JsValue::Object(element);
+    };
+
+    let Some(existing) = index.element(key) else {
+        console_warn!(
+            "hydration mismatch: could not find <{}> with key {}",
+            tag,
+            key
+        );
+        return js::create_element(tag);
+    };
+    let found = existing.get("tagName").to_string().unwrap_or_default();
+    if !found.eq_ignore_ascii_case(tag) {
+        console_warn!(
+            "hydration mismatch: expected <{}> with key {}, found <{}>",
+            tag,
+            key,
+            found.to_ascii_lowercase()
+        );
+        let element = js::create_element(tag).to_ref().unwrap();
+        js_fn!("{}.replaceWith({})").queue(&[existing.into(), element.clone().into()]);
+        return JsValue::Object(element);
+    }
+    claim(&existing);
+    JsValue::Object(existing)
+}
+
+// Creates the text node of dynamic text and appends it to the parent,
+// or claims the server-rendered text node while hydrating.
+pub(crate) fn create_text_node(parent: &ObjectRef) -> ObjectRef {
+    create_node(parent, true)
+}
+
+// Creates the comment that marks the position of a list and appends it to
+// the parent, or claims the server-rendered comment while hydrating.
+pub(crate) fn create_comment(parent: &ObjectRef) -> ObjectRef {
+    create_node(parent, false)
+}
+
+fn create_node(parent: &ObjectRef, text: bool) -> ObjectRef {
+    let Some((key, index)) = next_key() else {
+        return append_node(parent, text);
+    };
+    let Some(index) = index else {
+        // Text nodes are preceded by a comment, since adjacent text is merged when parsing HTML
+        let marker = append_node(parent, false);
+        js_fn!("{}.nodeValue = {}").queue(&[marker.clone().into(), format!("hk:{}", key).into()]);
+        return if text {
+            append_node(parent, true)
+        } else {
+            marker
+        };
+    };
+
+    let Some(marker) = index.comment(key) else {
+        console_warn!("hydration mismatch: could not find node with key {}", key);
+        return append_node(parent, text);
+    };
+    if !text {
+        return marker;
+    }
+    // Empty text is not rendered, so the text node may be missing
+    match marker.get("nextSibling").to_ref() {
+        Ok(node) if node.get("nodeType").to_num() == Ok(3.0) => node,
+        _ => {
+            let node = new_node(true);
+            js_fn!("{}.after({})").queue(&[marker.into(), node.clone().into()]);
+            node
+        }
+    }
+}
+
+fn new_node(text: bool) -> ObjectRef {
+    let node = if text {
+        js_fn!("return document.createTextNode('')").invoke(&[])
+    } else {
+        js_fn!("return document.createComment('')").invoke(&[])
+    };
+    node.to_ref().unwrap()
+}
+
+fn append_node(parent: &ObjectRef, text: bool) -> ObjectRef {
+    let node = new_node(text);
+    js_fn!("{}.appendChild({})").queue(&[parent.into(), node.clone().into()]);
+    node
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{native, signals::Signal};
+
+    fn counter(label: &'static str) -> impl FnOnce() -> Elem {
+        move || {
+            let count = Signal::new(0);
+            let items = Signal::new(vec![1, 2]);
+            Elem::new("div")
+                .class("counter")
+                .append(&Elem::new("span").text(label).text_dyn(count))
+                .append(&Elem::new("button").text("+").on("click", move |_| {
+                    count.update(|c| *c += 1);
+                    items.update(|items| items.push(items.len() + 1));
+                }))
+                .append(&Elem::new("ul").each(
+                    items,
+                    |i| *i,
+                    |i| Elem::new("li").text(&i.to_string()),
+                ))
+        }
+    }
+
+    #[test]
+    fn test_hydrate() {
+        // render on the "server"
+        let root = Elem::new("main");
+        let server = render(counter("Count: "));
+        root.clone().append(&server);
+        let html = native::inner_html(&root.element);
+        assert_eq!(
+            html,
+            "<div data-hk=\"0\" class=\"counter\"><span data-hk=\"1\">Count: <!--hk:2-->0</span>\
+             <button data-hk=\"3\">+</button><ul data-hk=\"4\"><li data-hk=\"6\">1</li>\
+             <li data-hk=\"7\">2</li><!--hk:5--></ul></div>"
+        );
+        drop(server);
+
+        // hydrating does not change the page
+        let elem = hydrate(&root.element, counter("Count: "));
+        assert_eq!(native::inner_html(&root.element), html);
+        assert!(native::take_console().is_empty());
+
+        // but binds the listeners and signals
+        let button = root
+            .element
+            .call("querySelector", &["button".into()])
+            .to_ref()
+            .unwrap();
+        native::dispatch_event(&button, "click");
+        assert_eq!(
+            native::outer_html(&elem.element.get("firstChild").to_ref().unwrap()),
+            "<span data-hk=\"1\">Count: <!--hk:2-->1</span>"
+        );
+        assert!(native::inner_html(&root.element).contains("<li>3</li><!--hk:5-->"));
+    }
+
+    #[test]
+    fn test_hydration_mismatch() {
+        let server = || Elem::new("div").append(&Elem::new("p").text("server"));
+        let root = Elem::new("main");
+        root.clone().append(&render(server));
+
+        // nodes that were not hydrated are reported
+        hydrate(&root.element, || Elem::new("div"));
+        let warnings = native::take_console();
+        assert_eq!(
+            warnings,
+            vec![(
+                "warn".to_owned(),
+                "hydration mismatch: the server rendered more than 1 nodes".to_owned()
+            )]
+        );
+
+        // the mismatched element is replaced by a new one, with the children of the client
+        let elem = hydrate(&root.element, || {
+            Elem::new("div").append(&Elem::new("h1").text("client"))
+        });
+        assert_eq!(
+            native::outer_html(&elem.element),
+            "<div data-hk=\"0\"><h1>client</h1></div>"
+        );
+        let warnings = native::take_console();
+        assert_eq!(
+            warnings[0].1,
+            "hydration mismatch: expected <h1> with key 1, found <p>"
+        );
+
+        // static text that differs from the server is reported, and the dynamic text is not compared
+        let server = || {
+            Elem::new("p")
+                .text("Hello, ")
+                .text_dyn(Signal::new("server"))
+                .text("!")
+        };
+        let root = Elem::new("main");
+        root.clone().append(&render(server));
+        hydrate(&root.element, || {
+            Elem::new("p")
+                .text("Hi, ")
+                .text_dyn(Signal::new("client"))
+                .text("!")
+        });
+        assert_eq!(
+            native::take_console(),
+            vec![(
+                "warn".to_owned(),
+                "hydration mismatch: expected text \"Hi, !\", found \"Hello, !\"".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_hydration_state() {
+        let root = Elem::new("main");
+        root.clone().append(&render(|| Elem::new("div")));
+
+        // the ids of dropped nodes are not claimed by other objects
+        let elem = hydrate(&root.element, || {
+            drop(Elem::new("div"));
+            Elem::from(js::create_element("p").to_ref().unwrap()).text("client")
+        });
+        assert_eq!(native::outer_html(&elem.element), "<p>client</p>");
+
+        // the previous state is restored if the component panics
+        let result = std::panic::catch_unwind(|| hydrate(&root.element, || panic!("component")));
+        assert!(result.is_err());
+        assert!(STATE.with_borrow(Option::is_none));
+    }
+}
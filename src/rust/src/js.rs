@@ -1,18 +1,41 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(feature = "serde")]
+pub use crate::convert::{from_js, to_js};
+use crate::{array::Array, closure::Closure, commands};
 
 thread_local! {
     // Buffer used to communicate from JS to Rust. Initial size is 1024.
     // Grows when `get_allocation` is called from JS with size bigger than current size.
     static ALLOCATION: RefCell<Vec<u8>> = RefCell::new(vec![0; 1024]);
+    // Number of calls from Rust to JS.
+    static CROSSINGS: Cell<u64> = const { Cell::new(0) };
 }
 
+#[cfg(target_arch = "wasm32")]
 extern "C" {
     fn __invoke(c_ptr: *const u8, c_len: u32, p_ptr: *const u8, p_len: u32);
+    fn __register_snippet(c_ptr: *const u8, c_len: u32) -> u32;
+    fn __invoke_snippet(id: u32, p_ptr: *const u8, p_len: u32);
     fn __free_object(id: u32);
     fn __create_element(t_ptr: *const u8, t_len: u32);
     fn __query_selector(q_ptr: *const u8, q_len: u32);
+    fn __read_deep(object_id: u32);
+    fn __get(object_id: u32, k_ptr: *const u8, k_len: u32);
+    fn __get_path(object_id: u32, p_ptr: *const u8, p_len: u32);
+    fn __set(object_id: u32, k_ptr: *const u8, k_len: u32, v_ptr: *const u8, v_len: u32);
+    fn __call(object_id: u32, m_ptr: *const u8, m_len: u32, a_ptr: *const u8, a_len: u32);
+    fn __has(object_id: u32, k_ptr: *const u8, k_len: u32);
+    fn __keys(object_id: u32);
+    fn __instance_of(object_id: u32, c_ptr: *const u8, c_len: u32);
 }
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::{
+    __call, __create_element, __free_object, __get, __get_path, __has, __instance_of, __invoke,
+    __invoke_snippet, __keys, __query_selector, __read_deep, __register_snippet, __set,
+};
 
 #[no_mangle]
 pub fn get_allocation(size: usize) -> *const u8 {
@@ -33,6 +56,12 @@ pub struct ObjectId(u32);
 
 impl Drop for ObjectId {
     fn drop(&mut self) {
+        // The object may still be used by queued calls
+        if commands::is_batching() {
+            commands::free_later(self.0);
+            return;
+        }
+        count_crossing();
         unsafe {
             __free_object(self.0);
         }
@@ -55,6 +84,91 @@ impl ObjectRef {
     }
 }
 
+// Property access. These use dedicated imports, so no JS code is compiled for them.
+// If JS throws, the error is logged to the console and Rust panics, like with `invoke`.
+impl ObjectRef {
+    pub fn get(&self, name: &str) -> JsValue {
+        prepare_call();
+        unsafe { __get(self.id(), name.as_ptr(), name.len() as u32) };
+
+        expect_response(read_response())
+    }
+
+    // Gets a nested property, for example `event.get_path("target.value")`.
+    // Returns `undefined` when one of the properties on the path is `null` or `undefined`.
+    pub fn get_path(&self, path: &str) -> JsValue {
+        prepare_call();
+        unsafe { __get_path(self.id(), path.as_ptr(), path.len() as u32) };
+
+        expect_response(read_response())
+    }
+
+    pub fn set(&self, name: &str, value: impl Into<JsValue>) {
+        let value = serialize(&[value.into()]);
+
+        prepare_call();
+        unsafe {
+            __set(
+                self.id(),
+                name.as_ptr(),
+                name.len() as u32,
+                value.as_ptr(),
+                value.len() as u32,
+            )
+        };
+
+        expect_response(read_response());
+    }
+
+    // Calls a method of the object, for example `element.call("focus", &[])`.
+    pub fn call(&self, method: &str, args: &[JsValue]) -> JsValue {
+        let args = serialize(args);
+
+        prepare_call();
+        unsafe {
+            __call(
+                self.id(),
+                method.as_ptr(),
+                method.len() as u32,
+                args.as_ptr(),
+                args.len() as u32,
+            )
+        };
+
+        expect_response(read_response())
+    }
+
+    // Whether the object has the property, same as `name in object`.
+    pub fn has(&self, name: &str) -> bool {
+        prepare_call();
+        unsafe { __has(self.id(), name.as_ptr(), name.len() as u32) };
+
+        expect_response(read_response()).to_bool().unwrap_or(false)
+    }
+
+    // Names of the own enumerable properties, same as `Object.keys(object)`.
+    pub fn keys(&self) -> Vec<String> {
+        prepare_call();
+        unsafe { __keys(self.id()) };
+
+        expect_response(read_response())
+            .to_vec()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|key| key.to_string().ok())
+            .collect()
+    }
+
+    // Whether the object is an instance of the global class with the given name,
+    // for example `target.instance_of("HTMLInputElement")`.
+    pub fn instance_of(&self, class: &str) -> bool {
+        prepare_call();
+        unsafe { __instance_of(self.id(), class.as_ptr(), class.len() as u32) };
+
+        expect_response(read_response()).to_bool().unwrap_or(false)
+    }
+}
+
 // NOTE: Numbers in Javascript are represented by 64-bits floats
 // https://tc39.es/ecma262/multipage/ecmascript-data-types-and-values.html#sec-ecmascript-language-types-number-type
 #[derive(Debug)]
@@ -68,12 +182,156 @@ pub enum JsValue {
     Object(ObjectRef),
     Blob(Vec<u8>),
     Array(ObjectRef),
+    // Arrays and plain objects that are copied, rather than referenced. They are created
+    // as new arrays and objects when passed to JS, see also `read_deep`.
+    List(Vec<JsValue>),
+    Dict(Vec<(String, JsValue)>),
+    // Typed arrays other than `Uint8Array`, which is a `Blob`.
+    TypedArray(TypedArray),
+    // Rust closure, which is passed to JS as a function.
+    Function(Closure),
 }
 
+// https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Global_Objects/TypedArray
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedArray {
+    Int8(Vec<i8>),
+    Int16(Vec<i16>),
+    Uint16(Vec<u16>),
+    Int32(Vec<i32>),
+    Uint32(Vec<u32>),
+    Float32(Vec<f32>),
+    Float64(Vec<f64>),
+    BigInt64(Vec<i64>),
+    BigUint64(Vec<u64>),
+}
+
+// The wire format of a typed array is its kind (the order of the variants of `TypedArray`),
+// the length in bytes and the elements in little endian.
+macro_rules! typed_arrays {
+    ($($kind:literal => $variant:ident($t:ty)),*) => {
+        impl TypedArray {
+            fn kind(&self) -> u8 {
+                match self {
+                    $(TypedArray::$variant(_) => $kind,)*
+                }
+            }
+
+            fn to_bytes(&self) -> Vec<u8> {
+                match self {
+                    $(TypedArray::$variant(v) => v.iter().flat_map(|x| x.to_le_bytes()).collect(),)*
+                }
+            }
+
+            fn from_bytes(kind: u8, bytes: &[u8]) -> Result<Self, &'static str> {
+                match kind {
+                    $($kind => Ok(TypedArray::$variant(
+                        bytes
+                            .chunks_exact(std::mem::size_of::<$t>())
+                            .map(|x| <$t>::from_le_bytes(x.try_into().unwrap()))
+                            .collect(),
+                    )),)*
+                    _ => Err("invalid typed array"),
+                }
+            }
+        }
+
+        $(
+            impl From<Vec<$t>> for JsValue {
+                fn from(v: Vec<$t>) -> Self {
+                    Self::TypedArray(TypedArray::$variant(v))
+                }
+            }
+
+            impl TryFrom<JsValue> for Vec<$t> {
+                type Error = &'static str;
+
+                fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+                    match value {
+                        JsValue::TypedArray(TypedArray::$variant(v)) => Ok(v),
+                        _ => Err("invalid type"),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl TypedArray {
+    // Elements as numbers, or as bigints for `BigInt64` and `BigUint64` arrays.
+    pub fn into_values(self) -> Vec<JsValue> {
+        fn map<T>(v: Vec<T>, f: impl Fn(T) -> JsValue) -> Vec<JsValue> {
+            v.into_iter().map(f).collect()
+        }
+        match self {
+            TypedArray::Int8(v) => map(v, |x| JsValue::Number(x as f64)),
+            TypedArray::Int16(v) => map(v, |x| JsValue::Number(x as f64)),
+            TypedArray::Uint16(v) => map(v, |x| JsValue::Number(x as f64)),
+            TypedArray::Int32(v) => map(v, |x| JsValue::Number(x as f64)),
+            TypedArray::Uint32(v) => map(v, |x| JsValue::Number(x as f64)),
+            TypedArray::Float32(v) => map(v, |x| JsValue::Number(x as f64)),
+            TypedArray::Float64(v) => map(v, JsValue::Number),
+            TypedArray::BigInt64(v) => map(v, JsValue::BigInt),
+            TypedArray::BigUint64(v) => map(v, |x| JsValue::BigInt(x as i64)),
+        }
+    }
+}
+
+typed_arrays! {
+    0 => Int8(i8),
+    1 => Int16(i16),
+    2 => Uint16(u16),
+    3 => Int32(i32),
+    4 => Uint32(u32),
+    5 => Float32(f32),
+    6 => Float64(f64),
+    7 => BigInt64(i64),
+    8 => BigUint64(u64)
+}
+
+// Exception thrown by JS, with the `name`, `message` and `stack` of the thrown error.
+#[derive(Debug, Clone)]
+pub struct JsError {
+    pub name: String,
+    pub message: String,
+    pub stack: String,
+}
+
+impl std::fmt::Display for JsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+impl std::error::Error for JsError {}
+
+// Invokes JS code, in which every `{}` is replaced by the next parameter. If the code throws,
+// the error is logged to the console and Rust panics. Use `try_invoke` to handle the error instead.
+// Not available with the `csp` feature, where code cannot be evaluated at runtime, so it always
+// fails with an `EvalError`. Use `js_fn!` instead. On native targets, see `native`, code other
+// than the snippets of this crate fails with a `NotSupportedError`.
 pub fn invoke(code: &str, params: &[JsValue]) -> JsValue {
-    let code = __code(code, params);
+    expect_response(try_invoke(code, params))
+}
+
+// Same as `invoke`, but returns the error thrown by JS, or the `EvalError` with the `csp` feature.
+pub fn try_invoke(code: &str, params: &[JsValue]) -> Result<JsValue, JsError> {
+    // Code that is only known at runtime cannot be evaluated under a strict CSP
+    if cfg!(feature = "csp") {
+        return Err(JsError {
+            name: "EvalError".to_owned(),
+            message: format!(
+                "cannot invoke `{}` with the `csp` feature, use `js_fn!` instead",
+                code
+            ),
+            stack: String::new(),
+        });
+    }
+
+    let code = __code(code);
     let params = serialize(params);
 
+    prepare_call();
     unsafe {
         __invoke(
             code.as_ptr(),
@@ -83,139 +341,411 @@ pub fn invoke(code: &str, params: &[JsValue]) -> JsValue {
         )
     };
 
-    let values = ALLOCATION
-        .with_borrow(|buffer| deserialize(buffer))
-        .expect("invalid response from JS");
+    read_response()
+}
+
+// JS code that is compiled once, when it is first invoked. After that, only the id
+// of the compiled function is sent to JS. Snippets are usually created with `js_fn!`:
+// `js_fn!("{}.setAttribute({},{})").invoke(&[element.into(), name.into(), value.into()])`
+//
+// With the `csp` feature, snippets are not compiled at runtime. Instead, `js_fn!` stores
+// the code in the `web_snippets` custom section of the wasm module, from which
+// `src/js/snippets.js` generates the functions ahead of time. Their ids are hashes of the code,
+// and the generation fails if two different snippets have the same id.
+pub struct Snippet {
+    code: &'static str,
+    // Id of the compiled function in JS, or 0 if the snippet is not registered yet
+    id: AtomicU32,
+}
+
+impl Snippet {
+    // Used by `js_fn!`, which also stores the code in the wasm module with the `csp` feature.
+    // A snippet created otherwise cannot be found with that feature.
+    #[doc(hidden)]
+    pub const fn __new(code: &'static str) -> Self {
+        let id = if cfg!(feature = "csp") {
+            snippet_id(code)
+        } else {
+            0
+        };
+        Self {
+            code,
+            id: AtomicU32::new(id),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub(crate) fn id(&self) -> u32 {
+        match self.id.load(Ordering::Relaxed) {
+            0 => {
+                let code = __code(self.code);
+                count_crossing();
+                let id = unsafe { __register_snippet(code.as_ptr(), code.len() as u32) };
+                self.id.store(id, Ordering::Relaxed);
+                id
+            }
+            id => id,
+        }
+    }
+
+    pub fn invoke(&self, params: &[JsValue]) -> JsValue {
+        expect_response(self.try_invoke(params))
+    }
+
+    pub fn try_invoke(&self, params: &[JsValue]) -> Result<JsValue, JsError> {
+        let id = self.id();
+        let params = serialize(params);
+
+        prepare_call();
+        unsafe { __invoke_snippet(id, params.as_ptr(), params.len() as u32) };
+
+        read_response()
+    }
+
+    // Invokes the snippet, ignoring its return value. When batching is enabled
+    // (see `commands::set_batching`), the call is queued instead.
+    pub fn queue(&self, params: &[JsValue]) {
+        commands::queue(self, params);
+    }
+}
+
+// FNV-1a hash of the code, which is never 0.
+pub const fn snippet_id(code: &str) -> u32 {
+    let bytes = code.as_bytes();
+    let mut hash: u32 = 0x811c9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x01000193);
+        i += 1;
+    }
+    if hash == 0 {
+        1
+    } else {
+        hash
+    }
+}
+
+// Entry of the `web_snippets` custom section: the id and length of the code
+// as u32 (little endian), followed by the code itself. `N` is `8 + code.len()`.
+#[doc(hidden)]
+pub const fn snippet_entry<const N: usize>(code: &str) -> [u8; N] {
+    let bytes = code.as_bytes();
+    let id = snippet_id(code).to_le_bytes();
+    let len = (bytes.len() as u32).to_le_bytes();
+    let mut entry = [0; N];
+    let mut i = 0;
+    while i < 4 {
+        entry[i] = id[i];
+        entry[4 + i] = len[i];
+        i += 1;
+    }
+    let mut i = 0;
+    while i < bytes.len() {
+        entry[8 + i] = bytes[i];
+        i += 1;
+    }
+    entry
+}
+
+// Creates a `&'static Snippet` for the given code.
+#[cfg(not(feature = "csp"))]
+#[macro_export]
+macro_rules! js_fn {
+    ($code:expr) => {{
+        static SNIPPET: $crate::js::Snippet = $crate::js::Snippet::__new($code);
+        &SNIPPET
+    }};
+}
+
+// Creates a `&'static Snippet` for the given code, and stores the code in the wasm module.
+#[cfg(feature = "csp")]
+#[macro_export]
+macro_rules! js_fn {
+    ($code:expr) => {{
+        const CODE: &str = $code;
+        #[cfg_attr(target_arch = "wasm32", link_section = "web_snippets")]
+        #[used]
+        static ENTRY: [u8; 8 + CODE.len()] = $crate::js::snippet_entry(CODE);
+        static SNIPPET: $crate::js::Snippet = $crate::js::Snippet::__new(CODE);
+        &SNIPPET
+    }};
+}
 
-    values.into_iter().next().unwrap_or(JsValue::Undefined)
+// Number of calls from Rust to JS so far, on the current thread.
+pub fn crossings() -> u64 {
+    CROSSINGS.get()
+}
+
+pub(crate) fn count_crossing() {
+    CROSSINGS.set(CROSSINGS.get() + 1);
+}
+
+// Calls that depend on the state of the page first flush the queued calls.
+pub(crate) fn prepare_call() {
+    commands::flush();
+    count_crossing();
 }
 
 pub fn query_selector(query: &str) -> JsValue {
+    prepare_call();
     unsafe { __query_selector(query.as_ptr(), query.len() as u32) };
 
-    let values = ALLOCATION
-        .with_borrow(|buffer| deserialize(buffer))
-        .expect("invalid response from JS");
-
-    values.into_iter().next().unwrap_or(JsValue::Undefined)
+    expect_response(read_response())
 }
 
 pub fn create_element(tag: &str) -> JsValue {
+    count_crossing();
     unsafe { __create_element(tag.as_ptr(), tag.len() as u32) };
 
-    let values = ALLOCATION
+    expect_response(read_response())
+}
+
+// Copies a JS array or plain object, including the arrays and plain objects
+// nested in it, into a `JsValue::List` or `JsValue::Dict`. Objects that are referenced
+// several times are copied each time. If the object contains itself, a `TypeError` is
+// logged to the console and Rust panics. Use `try_read_deep` to handle the error instead.
+pub fn read_deep(object: &ObjectRef) -> JsValue {
+    expect_response(try_read_deep(object))
+}
+
+pub fn try_read_deep(object: &ObjectRef) -> Result<JsValue, JsError> {
+    prepare_call();
+    unsafe { __read_deep(object.id()) };
+
+    read_response()
+}
+
+// Reads the value that JS has written to the allocation buffer.
+pub(crate) fn read_response() -> Result<JsValue, JsError> {
+    ALLOCATION.with_borrow(|buffer| deserialize_response(buffer))
+}
+
+// Reads the values that JS has written to the allocation buffer, for example the arguments of a closure.
+pub(crate) fn read_values() -> Vec<JsValue> {
+    ALLOCATION
         .with_borrow(|buffer| deserialize(buffer))
-        .expect("invalid response from JS");
+        .unwrap_or_default()
+}
+
+pub(crate) fn expect_response(response: Result<JsValue, JsError>) -> JsValue {
+    match response {
+        Ok(value) => value,
+        Err(error) => {
+            log_error(&error);
+            panic!("{}", error);
+        }
+    }
+}
+
+pub(crate) fn log_error(error: &JsError) {
+    let message = format!("{}\n{}", error, error.stack);
+    let _ = crate::js_fn!("console.error({})").try_invoke(&[message.into()]);
+}
+
+// A response is either a single value, or an error (0x0A) followed by its name, message and stack.
+fn deserialize_response(buffer: &[u8]) -> Result<JsValue, JsError> {
+    if buffer.get(4) == Some(&0x0A) {
+        let mut strings = Vec::new();
+        let mut i = 5;
+        for _ in 0..3 {
+            let len = u32::from_le_bytes(buffer[i..i + 4].try_into().unwrap()) as usize;
+            strings.push(String::from_utf8_lossy(&buffer[i + 4..i + 4 + len]).into_owned());
+            i += 4 + len;
+        }
+        let [name, message, stack] = strings.try_into().unwrap();
+        return Err(JsError {
+            name,
+            message,
+            stack,
+        });
+    }
 
-    values.into_iter().next().unwrap_or(JsValue::Undefined)
+    match deserialize(buffer) {
+        Ok(values) => Ok(values.into_iter().next().unwrap_or(JsValue::Undefined)),
+        Err(message) => Err(JsError {
+            name: "InvalidResponse".into(),
+            message: message.into(),
+            stack: String::new(),
+        }),
+    }
 }
 
-fn serialize(values: &[JsValue]) -> Vec<u8> {
+pub(crate) fn serialize(values: &[JsValue]) -> Vec<u8> {
     let mut buffer = Vec::new();
 
     buffer.extend(u32::to_le_bytes(values.len() as u32));
 
     for value in values {
-        match value {
-            JsValue::Undefined => buffer.push(0x00),
-            JsValue::Null => buffer.push(0x01),
-            JsValue::Bool(true) => buffer.push(0x02),
-            JsValue::Bool(false) => buffer.push(0x03),
-            JsValue::Number(f) => {
-                buffer.push(0x04);
-                buffer.extend(f64::to_le_bytes(*f));
-            }
-            JsValue::BigInt(i) => {
-                buffer.push(0x05);
-                buffer.extend(i64::to_le_bytes(*i));
-            }
-            JsValue::String(s) => {
-                buffer.push(0x06);
-                buffer.extend(u32::to_le_bytes(s.len() as u32));
-                buffer.extend(s.as_bytes());
-            }
-            JsValue::Array(r) => {
-                buffer.push(0x07);
-                buffer.extend(u32::to_le_bytes(r.id()));
-            }
-            JsValue::Object(r) => {
-                buffer.push(0x08);
-                buffer.extend(u32::to_le_bytes(r.id()));
+        serialize_value(&mut buffer, value);
+    }
+
+    buffer
+}
+
+fn serialize_value(buffer: &mut Vec<u8>, value: &JsValue) {
+    match value {
+        JsValue::Undefined => buffer.push(0x00),
+        JsValue::Null => buffer.push(0x01),
+        JsValue::Bool(true) => buffer.push(0x02),
+        JsValue::Bool(false) => buffer.push(0x03),
+        JsValue::Number(f) => {
+            buffer.push(0x04);
+            buffer.extend(f64::to_le_bytes(*f));
+        }
+        JsValue::BigInt(i) => {
+            buffer.push(0x05);
+            buffer.extend(i64::to_le_bytes(*i));
+        }
+        JsValue::String(s) => {
+            buffer.push(0x06);
+            serialize_str(buffer, s);
+        }
+        JsValue::Array(r) => {
+            buffer.push(0x07);
+            buffer.extend(u32::to_le_bytes(r.id()));
+        }
+        JsValue::Object(r) => {
+            buffer.push(0x08);
+            buffer.extend(u32::to_le_bytes(r.id()));
+        }
+        JsValue::Blob(v) => {
+            buffer.push(0x09);
+            buffer.extend(u32::to_le_bytes(v.len() as u32));
+            buffer.extend(v);
+        }
+        JsValue::List(values) => {
+            buffer.push(0x0B);
+            buffer.extend(u32::to_le_bytes(values.len() as u32));
+            for value in values {
+                serialize_value(buffer, value);
             }
-            JsValue::Blob(v) => {
-                buffer.push(0x09);
-                buffer.extend(u32::to_le_bytes(v.len() as u32));
-                buffer.extend(v);
+        }
+        JsValue::TypedArray(array) => {
+            let bytes = array.to_bytes();
+            buffer.push(0x0D);
+            buffer.push(array.kind());
+            buffer.extend(u32::to_le_bytes(bytes.len() as u32));
+            buffer.extend(bytes);
+        }
+        JsValue::Function(closure) => {
+            buffer.push(0x0E);
+            buffer.extend(u32::to_le_bytes(closure.id()));
+        }
+        JsValue::Dict(entries) => {
+            buffer.push(0x0C);
+            buffer.extend(u32::to_le_bytes(entries.len() as u32));
+            for (key, value) in entries {
+                serialize_str(buffer, key);
+                serialize_value(buffer, value);
             }
         }
     }
+}
 
-    buffer
+fn serialize_str(buffer: &mut Vec<u8>, s: &str) {
+    buffer.extend(u32::to_le_bytes(s.len() as u32));
+    buffer.extend(s.as_bytes());
 }
 
-fn deserialize(buffer: &[u8]) -> Result<Vec<JsValue>, &'static str> {
+pub(crate) fn deserialize(buffer: &[u8]) -> Result<Vec<JsValue>, &'static str> {
     let mut values = Vec::new();
 
-    let len = u32::from_le_bytes(buffer[0..4].try_into().unwrap()) as usize;
+    let len = read_u32(buffer, 0)? as usize;
 
     let mut i = 4;
 
     for _ in 0..len {
-        let (size, value) = match buffer[i] {
-            0x00 => (1, JsValue::Undefined),
-            0x01 => (1, JsValue::Null),
-            0x02 => (1, JsValue::Bool(true)),
-            0x03 => (1, JsValue::Bool(false)),
-            0x04 => (
-                9,
-                JsValue::Number(f64::from_le_bytes(buffer[i + 1..i + 9].try_into().unwrap())),
-            ),
-            0x05 => (
-                9,
-                JsValue::BigInt(i64::from_le_bytes(buffer[i + 1..i + 9].try_into().unwrap())),
-            ),
-            0x06 => {
-                let len = u32::from_le_bytes(buffer[i + 1..i + 5].try_into().unwrap()) as usize;
-                let str = String::from_utf8_lossy(&buffer[i + 5..i + 5 + len]);
-                (5 + len, JsValue::String(str.into()))
-            }
-            0x07 => (
-                5,
-                JsValue::Array(ObjectRef::new(u32::from_le_bytes(
-                    buffer[i + 1..i + 5].try_into().unwrap(),
-                ))),
-            ),
-            0x08 => (
-                5,
-                JsValue::Object(ObjectRef::new(u32::from_le_bytes(
-                    buffer[i + 1..i + 5].try_into().unwrap(),
-                ))),
-            ),
-            0x09 => {
-                let len = u32::from_le_bytes(buffer[i + 1..i + 5].try_into().unwrap()) as usize;
-                (5 + len, JsValue::Blob(buffer[i + 5..i + 5 + len].to_vec()))
-            }
-            _ => return Err("invalid type"),
-        };
-        i += size;
-        values.push(value);
+        values.push(deserialize_value(buffer, &mut i)?);
     }
 
     Ok(values)
 }
 
-fn __code(code: &str, params: &[JsValue]) -> String {
+// Reads the value at position `i`, and moves `i` to the end of the value.
+fn deserialize_value(buffer: &[u8], i: &mut usize) -> Result<JsValue, &'static str> {
+    let tag = *buffer.get(*i).ok_or("unexpected end of buffer")?;
+    *i += 1;
+    let value = match tag {
+        0x00 => JsValue::Undefined,
+        0x01 => JsValue::Null,
+        0x02 => JsValue::Bool(true),
+        0x03 => JsValue::Bool(false),
+        0x04 => JsValue::Number(f64::from_le_bytes(
+            read_bytes(buffer, i, 8)?.try_into().unwrap(),
+        )),
+        0x05 => JsValue::BigInt(i64::from_le_bytes(
+            read_bytes(buffer, i, 8)?.try_into().unwrap(),
+        )),
+        0x06 => JsValue::String(deserialize_str(buffer, i)?),
+        0x07 => JsValue::Array(ObjectRef::new(deserialize_u32(buffer, i)?)),
+        0x08 => JsValue::Object(ObjectRef::new(deserialize_u32(buffer, i)?)),
+        0x09 => {
+            let len = deserialize_u32(buffer, i)? as usize;
+            JsValue::Blob(read_bytes(buffer, i, len)?.to_vec())
+        }
+        0x0B => {
+            let len = deserialize_u32(buffer, i)? as usize;
+            let mut values = Vec::with_capacity(len);
+            for _ in 0..len {
+                values.push(deserialize_value(buffer, i)?);
+            }
+            JsValue::List(values)
+        }
+        0x0C => {
+            let len = deserialize_u32(buffer, i)? as usize;
+            let mut entries = Vec::with_capacity(len);
+            for _ in 0..len {
+                let key = deserialize_str(buffer, i)?;
+                entries.push((key, deserialize_value(buffer, i)?));
+            }
+            JsValue::Dict(entries)
+        }
+        0x0D => {
+            let kind = *read_bytes(buffer, i, 1)?.first().unwrap();
+            let len = deserialize_u32(buffer, i)? as usize;
+            JsValue::TypedArray(TypedArray::from_bytes(kind, read_bytes(buffer, i, len)?)?)
+        }
+        _ => return Err("invalid type"),
+    };
+    Ok(value)
+}
+
+fn read_u32(buffer: &[u8], i: usize) -> Result<u32, &'static str> {
+    let bytes = buffer.get(i..i + 4).ok_or("unexpected end of buffer")?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_bytes<'a>(buffer: &'a [u8], i: &mut usize, len: usize) -> Result<&'a [u8], &'static str> {
+    let bytes = buffer.get(*i..*i + len).ok_or("unexpected end of buffer")?;
+    *i += len;
+    Ok(bytes)
+}
+
+fn deserialize_u32(buffer: &[u8], i: &mut usize) -> Result<u32, &'static str> {
+    let value = read_u32(buffer, *i)?;
+    *i += 4;
+    Ok(value)
+}
+
+fn deserialize_str(buffer: &[u8], i: &mut usize) -> Result<String, &'static str> {
+    let len = deserialize_u32(buffer, i)? as usize;
+    Ok(String::from_utf8_lossy(read_bytes(buffer, i, len)?).into())
+}
+
+// Wraps the code in a function, in which every `{}` is a parameter. An empty object is written
+// with a space, as `{ }`. Same as `toFunction` in `snippets.js`.
+pub(crate) fn __code(code: &str) -> String {
     let mut code_params = String::from(code);
 
-    let params_names = params
-        .iter()
-        .enumerate()
-        .map(|(i, _)| "p".to_owned() + &i.to_string())
-        .collect::<Vec<_>>();
-    for param_name in &params_names {
-        if let Some(pos) = code_params.find("{}") {
-            code_params.replace_range(pos..pos + 2, param_name);
-        }
+    let mut params_names = Vec::new();
+    while let Some(pos) = code_params.find("{}") {
+        let param_name = format!("p{}", params_names.len());
+        code_params.replace_range(pos..pos + 2, &param_name);
+        params_names.push(param_name);
     }
     format!("function({}){{{}}}", params_names.join(","), code_params)
 }
@@ -275,6 +805,11 @@ impl From<&ObjectRef> for JsValue {
         Self::Object(s.to_owned())
     }
 }
+impl From<usize> for JsValue {
+    fn from(n: usize) -> Self {
+        Self::Number(n as f64)
+    }
+}
 impl From<Vec<u8>> for JsValue {
     fn from(s: Vec<u8>) -> Self {
         Self::Blob(s)
@@ -308,6 +843,23 @@ impl JsValue {
             _ => Err(format!("invalid type ({:?})", self)),
         }
     }
+    pub fn to_array(self) -> Result<Array, &'static str> {
+        match self {
+            JsValue::Array(s) => Ok(Array::from(s)),
+            _ => Err("invalid type"),
+        }
+    }
+
+    // Elements of an array. A referenced array is copied in a single call to JS.
+    pub fn to_vec(self) -> Result<Vec<JsValue>, &'static str> {
+        match self {
+            JsValue::List(values) => Ok(values),
+            JsValue::Array(s) => Ok(Array::from(s).to_vec()),
+            JsValue::TypedArray(array) => Ok(array.into_values()),
+            _ => Err("invalid type"),
+        }
+    }
+
     pub fn to_buffer(self) -> Result<Vec<u8>, &'static str> {
         match self {
             JsValue::Blob(s) => Ok(s),
@@ -322,3 +874,95 @@ impl JsValue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn serialize_strings(tag: u8, strings: &[&str]) -> Vec<u8> {
+        let mut buffer = vec![1, 0, 0, 0, tag];
+        for s in strings {
+            buffer.extend(u32::to_le_bytes(s.len() as u32));
+            buffer.extend(s.as_bytes());
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_deserialize_response() {
+        // value
+        let buffer = serialize_strings(0x06, &["hello"]);
+        let value = deserialize_response(&buffer).unwrap();
+        assert_eq!(value.to_string(), Ok("hello".to_owned()));
+
+        // error
+        let buffer = serialize_strings(0x0A, &["TypeError", "x is undefined", "f@main.js:1"]);
+        let error = deserialize_response(&buffer).unwrap_err();
+        assert_eq!(error.name, "TypeError");
+        assert_eq!(error.message, "x is undefined");
+        assert_eq!(error.stack, "f@main.js:1");
+        assert_eq!(error.to_string(), "TypeError: x is undefined");
+
+        // invalid type
+        let error = deserialize_response(&[1, 0, 0, 0, 0xFF]).unwrap_err();
+        assert_eq!(error.name, "InvalidResponse");
+    }
+
+    #[test]
+    fn test_snippet() {
+        // code is wrapped in a function with one parameter per placeholder
+        let snippet = Snippet::__new("{}.setAttribute({},{})");
+        let code = __code(snippet.code());
+        assert_eq!(code, "function(p0,p1,p2){p0.setAttribute(p1,p2)}");
+
+        // every `{}` is a parameter, also in `invoke`, and an empty object is written as `{ }`
+        let code = __code("const o = { }; o.x = {}; return o");
+        assert_eq!(code, "function(p0){const o = { }; o.x = p0; return o}");
+        let error = try_invoke("return {}", &[]).unwrap_err();
+        if cfg!(feature = "csp") {
+            assert_eq!(error.name, "EvalError");
+        } else {
+            assert_eq!(
+                error.message,
+                "cannot evaluate `function(p0){return p0}` without a browser"
+            );
+        }
+
+        // snippet is registered once
+        let id = snippet.id();
+        assert_ne!(id, 0);
+        assert_eq!(snippet.id(), id);
+    }
+
+    #[test]
+    fn test_snippet_entry() {
+        let code = "{}.remove()";
+        let entry: [u8; 19] = snippet_entry(code);
+        assert_eq!(entry[0..4], snippet_id(code).to_le_bytes());
+        assert_eq!(entry[4..8], 11u32.to_le_bytes());
+        assert_eq!(&entry[8..], code.as_bytes());
+
+        // ids of different snippets differ
+        assert_ne!(snippet_id(code), snippet_id("{}.append({})"));
+        assert_ne!(snippet_id(""), 0);
+    }
+
+    #[test]
+    fn test_typed_arrays() {
+        let buffer = serialize(&[
+            vec![1.5f32, -2.0].into(),
+            JsValue::List(vec![vec![-1i16, 300].into(), vec![u64::MAX].into()]),
+        ]);
+        let mut values = deserialize(&buffer).unwrap().into_iter();
+
+        let floats: Vec<f32> = values.next().unwrap().try_into().unwrap();
+        assert_eq!(floats, vec![1.5, -2.0]);
+
+        let mut list = values.next().unwrap().to_vec().unwrap().into_iter();
+        let shorts: Vec<i16> = list.next().unwrap().try_into().unwrap();
+        assert_eq!(shorts, vec![-1, 300]);
+        let longs: Result<Vec<i64>, _> = list.next().unwrap().try_into();
+        assert!(longs.is_err());
+    }
+}
@@ -1,5 +1,25 @@
+// The `html!` macro refers to this crate as `::web`
+extern crate self as web;
+
+pub use web_macros::{html, view};
+
+pub mod array;
 pub mod callbacks;
+pub mod closure;
+pub mod commands;
 pub mod components;
 pub mod console;
+#[cfg(feature = "serde")]
+mod convert;
 pub mod element;
+pub mod events;
+pub mod hydration;
 pub mod js;
+pub mod list;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native;
+pub mod router;
+pub mod runtime;
+pub mod signals;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ssr;
@@ -0,0 +1,197 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    rc::Rc,
+};
+
+use crate::{
+    element::Elem,
+    hydration, js_fn,
+    signals::{untrack, Reactive, Scope},
+};
+
+// A rendered item of a keyed list. The scope owns the signals and effects
+// that were created while rendering the item.
+struct Row<K> {
+    key: K,
+    elem: Elem,
+    scope: Scope,
+}
+
+impl Elem {
+    // Renders a list of items that is kept in sync with `items`. Items are identified
+    // by their key: when the list changes, existing elements are reused and moved,
+    // new items are rendered and removed items are removed from the page. Items with
+    // the same key reuse the elements of that key in order.
+    pub fn each<T, K: Eq + Hash + 'static>(
+        self,
+        items: impl Reactive<Vec<T>> + 'static,
+        key: impl Fn(&T) -> K + 'static,
+        render: impl Fn(&T) -> Elem + 'static,
+    ) -> Self {
+        // The items are rendered before a marker, so that the list can be mixed with other children
+        let marker = hydration::create_comment(&self.element);
+
+        let parent = self.element.clone();
+        // NOTE: the effect is owned by the element, so it only keeps a weak reference to it
+        let lifecycle = Rc::downgrade(&self.lifecycle);
+        let rows: Rc<RefCell<Vec<Row<K>>>> = Default::default();
+        self.lifecycle.bind(move || {
+            let items = items.get();
+
+            // Reuse the rows of existing keys, and render the new ones
+            let mut old_rows: HashMap<K, VecDeque<(usize, Elem, Scope)>> = HashMap::new();
+            for (i, row) in rows.take().into_iter().enumerate() {
+                let entry = (i, row.elem, row.scope);
+                old_rows.entry(row.key).or_default().push_back(entry);
+            }
+            let mut new_rows = Vec::with_capacity(items.len());
+            let mut old_indices = Vec::with_capacity(items.len());
+            for item in &items {
+                let k = key(item);
+                let old_row = old_rows.get_mut(&k).and_then(VecDeque::pop_front);
+                let (old_index, elem, scope) = match old_row {
+                    Some((i, elem, scope)) => (Some(i), elem, scope),
+                    None => {
+                        let scope = Scope::detached();
+                        let elem = untrack(|| scope.run(|| render(item)));
+                        (None, elem, scope)
+                    }
+                };
+                old_indices.push(old_index);
+                new_rows.push(Row {
+                    key: k,
+                    elem,
+                    scope,
+                });
+            }
+
+            // Remove the rows of keys that are gone
+            for (_, elem, scope) in old_rows.into_values().flatten() {
+                elem.remove();
+                drop(scope);
+            }
+
+            // Insert the new rows and move the existing ones, from the end to the start
+            let stable = longest_increasing_subsequence(&old_indices);
+            let mut anchor = marker.clone();
+            for ((row, stable), old_index) in new_rows.iter().zip(stable).zip(&old_indices).rev() {
+                if !stable {
+                    js_fn!("{}.insertBefore({},{})").queue(&[
+                        parent.clone().into(),
+                        row.elem.element.clone().into(),
+                        anchor.clone().into(),
+                    ]);
+                }
+                if let (None, Some(lifecycle)) = (old_index, lifecycle.upgrade()) {
+                    lifecycle.adopt(&row.elem);
+                }
+                anchor = row.elem.element.clone();
+            }
+
+            *rows.borrow_mut() = new_rows;
+        });
+        self
+    }
+}
+
+// Marks the items that are part of a longest increasing subsequence of their old indices.
+// These items keep their relative order, so only the other items have to be moved.
+fn longest_increasing_subsequence(sequence: &[Option<usize>]) -> Vec<bool> {
+    // `tails[l]` is the position of the smallest tail of an increasing subsequence of length `l + 1`
+    let mut tails: Vec<usize> = Vec::new();
+    let mut predecessors = vec![None; sequence.len()];
+    for (i, value) in sequence.iter().enumerate() {
+        let Some(value) = value else { continue };
+        let l = tails.partition_point(|&t| sequence[t] < Some(*value));
+        if l > 0 {
+            predecessors[i] = Some(tails[l - 1]);
+        }
+        if l == tails.len() {
+            tails.push(i);
+        } else {
+            tails[l] = i;
+        }
+    }
+
+    let mut stable = vec![false; sequence.len()];
+    let mut current = tails.last().copied();
+    while let Some(i) = current {
+        stable[i] = true;
+        current = predecessors[i];
+    }
+    stable
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{native::outer_html, signals::Signal};
+
+    #[test]
+    fn test_longest_increasing_subsequence() {
+        // moving the first item to the end only moves that item
+        let stable = longest_increasing_subsequence(&[Some(1), Some(2), Some(3), Some(4), Some(0)]);
+        assert_eq!(stable, vec![true, true, true, true, false]);
+
+        // new items are never stable
+        let stable = longest_increasing_subsequence(&[None, Some(0), None, Some(1)]);
+        assert_eq!(stable, vec![false, true, false, true]);
+
+        // swapping two items
+        let stable = longest_increasing_subsequence(&[Some(0), Some(3), Some(2), Some(1), Some(4)]);
+        assert_eq!(stable.iter().filter(|s| !**s).count(), 2);
+        assert!(stable[0] && stable[4]);
+
+        // reversed list
+        let stable = longest_increasing_subsequence(&[Some(2), Some(1), Some(0)]);
+        assert_eq!(stable.iter().filter(|s| **s).count(), 1);
+    }
+
+    #[test]
+    fn test_each() {
+        let items = Signal::new(vec![1, 2, 3]);
+        let list = Elem::new("ul").each(items, |i| *i, |i| Elem::new("li").text(&i.to_string()));
+        assert_eq!(
+            outer_html(&list.element),
+            "<ul><li>1</li><li>2</li><li>3</li><!----></ul>"
+        );
+
+        // existing rows are moved, not re-rendered
+        let first = list.element.get("firstChild").to_ref().unwrap();
+        items.set(vec![3, 1, 4]);
+        assert_eq!(
+            outer_html(&list.element),
+            "<ul><li>3</li><li>1</li><li>4</li><!----></ul>"
+        );
+        assert_eq!(outer_html(&first), "<li>1</li>");
+        assert!(first.get("parentNode").to_ref().is_ok());
+    }
+
+    #[test]
+    fn test_each_duplicate_keys() {
+        let items = Signal::new(vec![(1, "a"), (1, "b"), (2, "c")]);
+        let render = |item: &(i32, &str)| Elem::new("li").text(item.1);
+        let list = Elem::new("ul").each(items, |item| item.0, render);
+        assert_eq!(
+            outer_html(&list.element),
+            "<ul><li>a</li><li>b</li><li>c</li><!----></ul>"
+        );
+
+        // rows with the same key are reused in order, and the others are removed
+        items.set(vec![(2, "d"), (1, "e")]);
+        assert_eq!(
+            outer_html(&list.element),
+            "<ul><li>c</li><li>a</li><!----></ul>"
+        );
+        items.set(vec![(1, "f"), (1, "g"), (1, "h")]);
+        assert_eq!(
+            outer_html(&list.element),
+            "<ul><li>a</li><li>g</li><li>h</li><!----></ul>"
+        );
+        items.set(vec![]);
+        assert_eq!(outer_html(&list.element), "<ul><!----></ul>");
+    }
+}
@@ -0,0 +1,2257 @@
+// Events and promises of the mock DOM are only created by the test helpers
+#![cfg_attr(not(any(test, feature = "testing")), allow(dead_code))]
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::{
+    callbacks, commands, hydration,
+    js::{self, JsValue, ObjectRef},
+    router, runtime,
+};
+
+// Host backend for native targets. The imports of `main.js` are implemented by an
+// in-process mock DOM, so that `Elem`, signal bindings and callbacks can run under `cargo test`.
+// JS code is not evaluated: only the snippets used by this crate are supported (see `SNIPPETS`),
+// and `invoke` with any other code fails with a `NotSupportedError`.
+//
+// The mock DOM is a tree of elements, text nodes and comments, rooted at `<html>` with a `<head>`
+// and a `<body>`. Use `outer_html` to inspect it, and `dispatch_event` to trigger event listeners.
+// Except for `outer_html` and `run_microtasks`, which are used by `ssr`, these helpers are only
+// available in the tests of this crate, or with the `testing` feature.
+thread_local! {
+    static HOST: RefCell<Host> = RefCell::new(Host::new());
+    // Code of the registered snippets that are not supported, see `__register_snippet`.
+    static UNSUPPORTED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+// Objects that can be referenced from Rust, indexed into the tables of the host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Object {
+    Node(usize),
+    Event(usize),
+    Array(usize),
+    Plain(usize),
+    Function(u32),
+    Promise(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Undefined,
+    Null,
+    Bool(bool),
+    Number(f64),
+    BigInt(i64),
+    String(String),
+    Ref(Object),
+    Bytes(Vec<u8>),
+    TypedArray(u8, Vec<u8>),
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Element(String),
+    Text(String),
+    Comment(String),
+    // `document` and `window`, which receive the events that bubble up from the page
+    Document,
+    Window,
+}
+
+#[derive(Debug)]
+struct Node {
+    kind: NodeKind,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    attributes: Vec<(String, String)>,
+    properties: Vec<(String, Value)>,
+    listeners: Vec<Listener>,
+}
+
+impl Node {
+    fn new(kind: NodeKind) -> Self {
+        Self {
+            kind,
+            parent: None,
+            children: Vec::new(),
+            attributes: Vec::new(),
+            properties: Vec::new(),
+            listeners: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Listener {
+    event: String,
+    callback_id: u32,
+    once: bool,
+    capture: bool,
+}
+
+#[derive(Debug)]
+enum Promise {
+    // Future ids of the `JsFuture`s that await the promise
+    Pending(Vec<u32>),
+    Settled(Value, bool),
+}
+
+#[derive(Debug)]
+struct Event {
+    event_type: String,
+    target: usize,
+    current_target: Option<usize>,
+    default_prevented: bool,
+    stopped: bool,
+    stopped_immediately: bool,
+    properties: Vec<(String, Value)>,
+}
+
+// Same as the `name`, `message` and `stack` of a JS error, without the stack.
+#[derive(Debug)]
+struct Error {
+    name: &'static str,
+    message: String,
+}
+
+fn error(name: &'static str, message: impl Into<String>) -> Error {
+    Error {
+        name,
+        message: message.into(),
+    }
+}
+
+#[derive(Debug)]
+struct Host {
+    // Same as the `objects` and `free` arrays in `main.js`
+    objects: Vec<Option<Object>>,
+    free: Vec<u32>,
+    nodes: Vec<Node>,
+    events: Vec<Event>,
+    arrays: Vec<Vec<Value>>,
+    plains: Vec<Vec<(String, Value)>>,
+    // Node of each event listener, indexed by callback id
+    listeners: HashMap<u32, usize>,
+    tasks_scheduled: bool,
+    promises: Vec<Promise>,
+    // Futures that are resolved in the next call to `run_microtasks`,
+    // with the value of their promise and whether it was fulfilled
+    settled: Vec<(Value, u32, bool)>,
+    closure_result: Value,
+    console: Vec<(String, String)>,
+    // URL of the page without the origin, and the URLs of the session history
+    location: String,
+    history: Vec<String>,
+    history_index: usize,
+    // Slots of the nodes, events, arrays and plain objects that were freed by `collect`
+    free_nodes: Vec<usize>,
+    free_events: Vec<usize>,
+    free_arrays: Vec<usize>,
+    free_plains: Vec<usize>,
+    // Number of nodes, events, arrays and plain objects created since the last `collect`,
+    // and the number after which it runs again
+    created: usize,
+    collect_at: usize,
+    // Number of events that are being dispatched, during which `collect` does not run
+    dispatching: usize,
+}
+
+const HTML: usize = 0;
+const DOCUMENT: usize = 3;
+const WINDOW: usize = 4;
+
+// Number of nodes, events, arrays and plain objects that are created before the first `collect`.
+const COLLECT_MIN: usize = 1000;
+
+impl Host {
+    fn new() -> Self {
+        let mut host = Self {
+            objects: Vec::new(),
+            free: Vec::new(),
+            nodes: Vec::new(),
+            events: Vec::new(),
+            arrays: Vec::new(),
+            plains: Vec::new(),
+            listeners: HashMap::new(),
+            tasks_scheduled: false,
+            promises: Vec::new(),
+            settled: Vec::new(),
+            closure_result: Value::Undefined,
+            console: Vec::new(),
+            location: "/".to_owned(),
+            history: vec!["/".to_owned()],
+            history_index: 0,
+            free_nodes: Vec::new(),
+            free_events: Vec::new(),
+            free_arrays: Vec::new(),
+            free_plains: Vec::new(),
+            created: 0,
+            collect_at: COLLECT_MIN,
+            dispatching: 0,
+        };
+        let html = host.create(NodeKind::Element("html".into()));
+        let head = host.create(NodeKind::Element("head".into()));
+        let body = host.create(NodeKind::Element("body".into()));
+        host.nodes[html].children = vec![head, body];
+        host.nodes[head].parent = Some(html);
+        host.nodes[body].parent = Some(html);
+        host.create(NodeKind::Document);
+        host.create(NodeKind::Window);
+        host
+    }
+
+    fn store(&mut self, object: Object) -> u32 {
+        match self.free.pop() {
+            Some(id) => {
+                self.objects[id as usize] = Some(object);
+                id
+            }
+            None => {
+                self.objects.push(Some(object));
+                (self.objects.len() - 1) as u32
+            }
+        }
+    }
+
+    fn object(&self, id: u32) -> Result<Object, Error> {
+        self.objects
+            .get(id as usize)
+            .copied()
+            .flatten()
+            .ok_or_else(|| error("TypeError", format!("object {} is undefined", id)))
+    }
+
+    fn free_object(&mut self, id: u32) {
+        if let Some(object) = self.objects.get_mut(id as usize) {
+            if object.take().is_some() {
+                self.free.push(id);
+            }
+        }
+    }
+
+    fn new_event(&mut self, event: Event) -> usize {
+        self.created += 1;
+        allocate(&mut self.events, &mut self.free_events, event)
+    }
+
+    fn new_array(&mut self, values: Vec<Value>) -> Value {
+        self.created += 1;
+        let array = allocate(&mut self.arrays, &mut self.free_arrays, values);
+        Value::Ref(Object::Array(array))
+    }
+
+    fn new_plain(&mut self, entries: Vec<(String, Value)>) -> Value {
+        self.created += 1;
+        let plain = allocate(&mut self.plains, &mut self.free_plains, entries);
+        Value::Ref(Object::Plain(plain))
+    }
+}
+
+// The objects referenced by the values.
+fn refs<'a>(values: impl IntoIterator<Item = &'a Value> + 'a) -> impl Iterator<Item = Object> + 'a {
+    values.into_iter().filter_map(|value| match value {
+        Value::Ref(object) => Some(*object),
+        _ => None,
+    })
+}
+
+// Stores a value in a free slot, or at the end.
+fn allocate<T>(slots: &mut Vec<T>, free: &mut Vec<usize>, value: T) -> usize {
+    match free.pop() {
+        Some(i) => {
+            slots[i] = value;
+            i
+        }
+        None => {
+            slots.push(value);
+            slots.len() - 1
+        }
+    }
+}
+
+// Like the garbage collector of a browser, the host frees the nodes, events, arrays and plain
+// objects that can no longer be reached, so that its tables do not grow with every object that
+// was ever created. They are reached from the objects referenced by Rust, from the page, and
+// from the values of the host. This runs before a call, and not while an event is dispatched,
+// since the host refers to the nodes of the event by index in the meantime.
+impl Host {
+    fn collect_if_needed(&mut self) {
+        if self.dispatching == 0 && self.created >= self.collect_at {
+            self.collect();
+        }
+    }
+
+    fn collect(&mut self) {
+        let mut nodes = vec![false; self.nodes.len()];
+        let mut events = vec![false; self.events.len()];
+        let mut arrays = vec![false; self.arrays.len()];
+        let mut plains = vec![false; self.plains.len()];
+        // Free slots are skipped, as if they were reached
+        for (marks, free) in [
+            (&mut nodes, &self.free_nodes),
+            (&mut events, &self.free_events),
+            (&mut arrays, &self.free_arrays),
+            (&mut plains, &self.free_plains),
+        ] {
+            for i in free {
+                marks[*i] = true;
+            }
+        }
+
+        let mut stack: Vec<Object> = self.objects.iter().flatten().copied().collect();
+        stack.extend([HTML, DOCUMENT, WINDOW].map(Object::Node));
+        stack.extend(refs([&self.closure_result]));
+        stack.extend(refs(self.settled.iter().map(|(value, _, _)| value)));
+        stack.extend(refs(self.promises.iter().filter_map(
+            |promise| match promise {
+                Promise::Settled(value, _) => Some(value),
+                Promise::Pending(_) => None,
+            },
+        )));
+        while let Some(object) = stack.pop() {
+            match object {
+                Object::Node(n) if !nodes[n] => {
+                    nodes[n] = true;
+                    let node = &self.nodes[n];
+                    stack.extend(node.parent.map(Object::Node));
+                    stack.extend(node.children.iter().map(|c| Object::Node(*c)));
+                    stack.extend(refs(node.properties.iter().map(|(_, value)| value)));
+                }
+                Object::Event(e) if !events[e] => {
+                    events[e] = true;
+                    let event = &self.events[e];
+                    stack.push(Object::Node(event.target));
+                    stack.extend(event.current_target.map(Object::Node));
+                    stack.extend(refs(event.properties.iter().map(|(_, value)| value)));
+                }
+                Object::Array(a) if !arrays[a] => {
+                    arrays[a] = true;
+                    stack.extend(refs(&self.arrays[a]));
+                }
+                Object::Plain(p) if !plains[p] => {
+                    plains[p] = true;
+                    stack.extend(refs(self.plains[p].iter().map(|(_, value)| value)));
+                }
+                _ => {}
+            }
+        }
+
+        // The listeners of the freed nodes can never be called
+        for n in (0..nodes.len()).filter(|n| !nodes[*n]) {
+            let node = std::mem::replace(
+                &mut self.nodes[n],
+                Node::new(NodeKind::Comment(String::new())),
+            );
+            for listener in node.listeners {
+                self.listeners.remove(&listener.callback_id);
+            }
+            self.free_nodes.push(n);
+        }
+        for e in (0..events.len()).filter(|e| !events[*e]) {
+            self.events[e].properties = Vec::new();
+            self.free_events.push(e);
+        }
+        for a in (0..arrays.len()).filter(|a| !arrays[*a]) {
+            self.arrays[a] = Vec::new();
+            self.free_arrays.push(a);
+        }
+        for p in (0..plains.len()).filter(|p| !plains[*p]) {
+            self.plains[p] = Vec::new();
+            self.free_plains.push(p);
+        }
+
+        let live = self.nodes.len() - self.free_nodes.len() + self.events.len()
+            - self.free_events.len()
+            + self.arrays.len()
+            - self.free_arrays.len()
+            + self.plains.len()
+            - self.free_plains.len();
+        self.created = 0;
+        self.collect_at = live.max(COLLECT_MIN);
+    }
+}
+
+// Wire format, see `serialize` and `deserialize` in `main.js`.
+impl Host {
+    // Arrays and plain objects are copied up to the given depth, and referenced below it.
+    fn serialize(&mut self, values: &[Value], depth: u32) -> Vec<u8> {
+        let mut buffer = u32::to_le_bytes(values.len() as u32).to_vec();
+        for value in values {
+            self.serialize_value(&mut buffer, value, depth);
+        }
+        buffer
+    }
+
+    fn serialize_value(&mut self, buffer: &mut Vec<u8>, value: &Value, depth: u32) {
+        match value {
+            Value::Undefined => buffer.push(0x00),
+            Value::Null => buffer.push(0x01),
+            Value::Bool(true) => buffer.push(0x02),
+            Value::Bool(false) => buffer.push(0x03),
+            Value::Number(f) => {
+                buffer.push(0x04);
+                buffer.extend(f64::to_le_bytes(*f));
+            }
+            Value::BigInt(i) => {
+                buffer.push(0x05);
+                buffer.extend(i64::to_le_bytes(*i));
+            }
+            Value::String(s) => {
+                buffer.push(0x06);
+                serialize_str(buffer, s);
+            }
+            Value::Bytes(bytes) => {
+                buffer.push(0x09);
+                buffer.extend(u32::to_le_bytes(bytes.len() as u32));
+                buffer.extend(bytes);
+            }
+            Value::TypedArray(kind, bytes) => {
+                buffer.push(0x0D);
+                buffer.push(*kind);
+                buffer.extend(u32::to_le_bytes(bytes.len() as u32));
+                buffer.extend(bytes);
+            }
+            Value::Ref(Object::Array(i)) if depth > 0 => {
+                let values = self.arrays[*i].clone();
+                buffer.push(0x0B);
+                buffer.extend(u32::to_le_bytes(values.len() as u32));
+                for value in &values {
+                    self.serialize_value(buffer, value, depth - 1);
+                }
+            }
+            Value::Ref(Object::Plain(i)) if depth > 0 => {
+                let entries = self.plains[*i].clone();
+                buffer.push(0x0C);
+                buffer.extend(u32::to_le_bytes(entries.len() as u32));
+                for (key, value) in &entries {
+                    serialize_str(buffer, key);
+                    self.serialize_value(buffer, value, depth - 1);
+                }
+            }
+            Value::Ref(object) => {
+                buffer.push(if matches!(object, Object::Array(_)) {
+                    0x07
+                } else {
+                    0x08
+                });
+                buffer.extend(u32::to_le_bytes(self.store(*object)));
+            }
+        }
+    }
+
+    fn deserialize(&mut self, buffer: &[u8]) -> Result<Vec<Value>, Error> {
+        let mut reader = Reader { buffer, i: 0 };
+        let len = reader.u32()?;
+        (0..len)
+            .map(|_| self.deserialize_value(&mut reader))
+            .collect()
+    }
+
+    fn deserialize_value(&mut self, reader: &mut Reader) -> Result<Value, Error> {
+        let value = match reader.bytes(1)?[0] {
+            0x00 => Value::Undefined,
+            0x01 => Value::Null,
+            0x02 => Value::Bool(true),
+            0x03 => Value::Bool(false),
+            0x04 => Value::Number(f64::from_le_bytes(reader.bytes(8)?.try_into().unwrap())),
+            0x05 => Value::BigInt(i64::from_le_bytes(reader.bytes(8)?.try_into().unwrap())),
+            0x06 => Value::String(reader.string()?),
+            0x07 | 0x08 => match self.objects.get(reader.u32()? as usize) {
+                Some(Some(object)) => Value::Ref(*object),
+                _ => Value::Undefined,
+            },
+            0x09 => {
+                let len = reader.u32()? as usize;
+                Value::Bytes(reader.bytes(len)?.to_vec())
+            }
+            0x0B => {
+                let len = reader.u32()?;
+                let values = (0..len)
+                    .map(|_| self.deserialize_value(reader))
+                    .collect::<Result<_, _>>()?;
+                self.new_array(values)
+            }
+            0x0C => {
+                let len = reader.u32()?;
+                let entries = (0..len)
+                    .map(|_| Ok((reader.string()?, self.deserialize_value(reader)?)))
+                    .collect::<Result<_, _>>()?;
+                self.new_plain(entries)
+            }
+            0x0D => {
+                let kind = reader.bytes(1)?[0];
+                let len = reader.u32()? as usize;
+                Value::TypedArray(kind, reader.bytes(len)?.to_vec())
+            }
+            0x0E => Value::Ref(Object::Function(reader.u32()?)),
+            tag => {
+                return Err(error(
+                    "Error",
+                    format!("invalid parameter type (0x{:x})", tag),
+                ))
+            }
+        };
+        Ok(value)
+    }
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .buffer
+            .get(self.i..self.i + len)
+            .ok_or_else(|| error("RangeError", "unexpected end of buffer"))?;
+        self.i += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+}
+
+fn serialize_str(buffer: &mut Vec<u8>, s: &str) {
+    buffer.extend(u32::to_le_bytes(s.len() as u32));
+    buffer.extend(s.as_bytes());
+}
+
+fn serialize_error(error: &Error) -> Vec<u8> {
+    let mut buffer = vec![1, 0, 0, 0, 0x0A];
+    serialize_str(&mut buffer, error.name);
+    serialize_str(&mut buffer, &error.message);
+    serialize_str(&mut buffer, "");
+    buffer
+}
+
+// Same as `String(value)` in JS.
+fn to_display(value: &Value) -> String {
+    match value {
+        Value::Undefined => "undefined".into(),
+        Value::Null => "null".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(f) if f.fract() == 0.0 && f.abs() < 1e21 => format!("{}", *f as i64),
+        Value::Number(f) => f.to_string(),
+        Value::BigInt(i) => i.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Ref(Object::Function(_)) => "function () { [native code] }".into(),
+        _ => "[object Object]".into(),
+    }
+}
+
+fn node(value: &Value) -> Result<usize, Error> {
+    match value {
+        Value::Ref(Object::Node(node)) => Ok(*node),
+        _ => Err(error("TypeError", "parameter is not of type 'Node'")),
+    }
+}
+
+fn arg(args: &[Value], i: usize) -> &Value {
+    args.get(i).unwrap_or(&Value::Undefined)
+}
+
+// DOM operations.
+impl Host {
+    fn create(&mut self, kind: NodeKind) -> usize {
+        self.created += 1;
+        allocate(&mut self.nodes, &mut self.free_nodes, Node::new(kind))
+    }
+
+    fn is_element(&self, node: usize) -> bool {
+        matches!(self.nodes[node].kind, NodeKind::Element(_))
+    }
+
+    fn tag(&self, node: usize) -> Option<&str> {
+        match &self.nodes[node].kind {
+            NodeKind::Element(tag) => Some(tag),
+            _ => None,
+        }
+    }
+
+    // Strings are appended as text nodes, like `Element.append`.
+    fn child_node(&mut self, value: &Value) -> usize {
+        match value {
+            Value::Ref(Object::Node(node)) => *node,
+            value => self.create(NodeKind::Text(to_display(value))),
+        }
+    }
+
+    fn detach(&mut self, node: usize) {
+        if let Some(parent) = self.nodes[node].parent.take() {
+            self.nodes[parent].children.retain(|child| *child != node);
+        }
+    }
+
+    fn insert(&mut self, parent: usize, child: usize, before: Option<usize>) -> Result<(), Error> {
+        if !self.is_element(parent) || self.ancestors(parent).contains(&child) {
+            return Err(error(
+                "HierarchyRequestError",
+                "the new child cannot be inserted here",
+            ));
+        }
+        if before == Some(child) {
+            return Ok(());
+        }
+        self.detach(child);
+        let index = match before {
+            Some(before) => self.nodes[parent]
+                .children
+                .iter()
+                .position(|c| *c == before)
+                .ok_or_else(|| error("NotFoundError", "the node before which the new node is to be inserted is not a child of this node"))?,
+            None => self.nodes[parent].children.len(),
+        };
+        self.nodes[parent].children.insert(index, child);
+        self.nodes[child].parent = Some(parent);
+        Ok(())
+    }
+
+    fn clear(&mut self, node: usize) {
+        for child in std::mem::take(&mut self.nodes[node].children) {
+            self.nodes[child].parent = None;
+        }
+    }
+
+    // The node itself, followed by its parent, grandparent, etc.
+    fn ancestors(&self, node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        while let Some(parent) = self.nodes[*path.last().unwrap()].parent {
+            path.push(parent);
+        }
+        path
+    }
+
+    fn sibling(&self, node: usize, offset: isize) -> Value {
+        let Some(parent) = self.nodes[node].parent else {
+            return Value::Null;
+        };
+        let children = &self.nodes[parent].children;
+        let index = children.iter().position(|c| *c == node).unwrap() as isize + offset;
+        match usize::try_from(index).ok().and_then(|i| children.get(i)) {
+            Some(sibling) => Value::Ref(Object::Node(*sibling)),
+            None => Value::Null,
+        }
+    }
+
+    fn attribute(&self, node: usize, name: &str) -> Option<&str> {
+        self.nodes[node]
+            .attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn set_attribute(&mut self, node: usize, name: &str, value: String) {
+        let attributes = &mut self.nodes[node].attributes;
+        match attributes.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => attributes.push((name.to_ascii_lowercase(), value)),
+        }
+    }
+
+    fn remove_attribute(&mut self, node: usize, name: &str) {
+        self.nodes[node].attributes.retain(|(n, _)| n != name);
+    }
+
+    fn classes(&self, node: usize) -> Vec<String> {
+        self.attribute(node, "class")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn toggle_class(&mut self, node: usize, class: &str, enabled: bool) {
+        let mut classes = self.classes(node);
+        classes.retain(|c| c != class);
+        if enabled {
+            classes.push(class.to_owned());
+        }
+        self.set_attribute(node, "class", classes.join(" "));
+    }
+
+    // Styles are stored in the `style` attribute, for example `color: red; width: 10px;`.
+    fn set_style(&mut self, node: usize, property: &str, value: &str) {
+        let mut styles: Vec<(String, String)> = self
+            .attribute(node, "style")
+            .unwrap_or_default()
+            .split(';')
+            .filter_map(|style| style.split_once(':'))
+            .map(|(p, v)| (p.trim().to_owned(), v.trim().to_owned()))
+            .collect();
+        match styles.iter_mut().find(|(p, _)| p == property) {
+            Some((_, v)) if value.is_empty() => *v = String::new(),
+            Some((_, v)) => *v = value.to_owned(),
+            None => styles.push((property.to_owned(), value.to_owned())),
+        }
+        let style = styles
+            .iter()
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(p, v)| format!("{}: {};", p, v))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.set_attribute(node, "style", style);
+    }
+
+    fn text_content(&self, node: usize) -> String {
+        match &self.nodes[node].kind {
+            NodeKind::Text(text) | NodeKind::Comment(text) => text.clone(),
+            NodeKind::Element(_) => self.nodes[node]
+                .children
+                .iter()
+                .filter(|c| !matches!(self.nodes[**c].kind, NodeKind::Comment(_)))
+                .map(|c| self.text_content(*c))
+                .collect(),
+            NodeKind::Document | NodeKind::Window => String::new(),
+        }
+    }
+
+    fn html(&self, node: usize, outer: bool) -> String {
+        let mut html = String::new();
+        if outer {
+            self.write_html(&mut html, node);
+        } else {
+            for child in &self.nodes[node].children {
+                self.write_html(&mut html, *child);
+            }
+        }
+        html
+    }
+
+    fn write_html(&self, html: &mut String, node: usize) {
+        match &self.nodes[node].kind {
+            NodeKind::Text(text) => html.push_str(&escape(text, false)),
+            NodeKind::Comment(text) => html.push_str(&format!("<!--{}-->", text)),
+            NodeKind::Element(tag) => {
+                html.push('<');
+                html.push_str(tag);
+                for (name, value) in &self.nodes[node].attributes {
+                    html.push_str(&format!(" {}=\"{}\"", name, escape(value, true)));
+                }
+                html.push('>');
+                if VOID_ELEMENTS.contains(&tag.as_str()) {
+                    return;
+                }
+                for child in &self.nodes[node].children {
+                    self.write_html(html, *child);
+                }
+                html.push_str(&format!("</{}>", tag));
+            }
+            NodeKind::Document | NodeKind::Window => {}
+        }
+    }
+
+    // The first descendant of `root` that matches the selector, in document order.
+    fn query_selector(
+        &self,
+        root: usize,
+        selector: &str,
+        include_root: bool,
+    ) -> Result<Value, Error> {
+        let selector = Selector::parse(selector)?;
+        let mut stack = if include_root {
+            vec![root]
+        } else {
+            self.nodes[root].children.iter().rev().copied().collect()
+        };
+        while let Some(node) = stack.pop() {
+            if selector.matches(self, node) {
+                return Ok(Value::Ref(Object::Node(node)));
+            }
+            stack.extend(self.nodes[node].children.iter().rev());
+        }
+        Ok(Value::Null)
+    }
+}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+fn escape(s: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            '<' if !attribute => escaped.push_str("&lt;"),
+            '>' if !attribute => escaped.push_str("&gt;"),
+            '\u{a0}' => escaped.push_str("&nbsp;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Host {
+    // Whether an array or plain object contains itself, directly or through
+    // the arrays and plain objects nested in it.
+    fn is_cyclic(&self, object: Object, ancestors: &mut Vec<Object>) -> bool {
+        let values: Vec<&Value> = match object {
+            Object::Array(i) => self.arrays[i].iter().collect(),
+            Object::Plain(i) => self.plains[i].iter().map(|(_, value)| value).collect(),
+            _ => return false,
+        };
+        if ancestors.contains(&object) {
+            return true;
+        }
+        ancestors.push(object);
+        let cyclic = values.into_iter().any(|value| match value {
+            Value::Ref(child) => self.is_cyclic(*child, ancestors),
+            _ => false,
+        });
+        ancestors.pop();
+        cyclic
+    }
+}
+
+// Location and session history. URLs are kept without the origin, as in `/users/1?tab=2#top`.
+impl Host {
+    // Resolves a URL against the location, like the `href` of a link.
+    fn resolve(&self, url: &str) -> String {
+        let (path, search, _) = split_url(&self.location);
+        if url.starts_with('/') {
+            url.to_owned()
+        } else if url.starts_with('#') {
+            format!("{}{}{}", path, search, url)
+        } else if url.starts_with('?') {
+            format!("{}{}", path, url)
+        } else {
+            let directory = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+            format!("{}{}", directory, url)
+        }
+    }
+
+    fn push_state(&mut self, url: &str) {
+        self.location = self.resolve(url);
+        self.history.truncate(self.history_index + 1);
+        self.history.push(self.location.clone());
+        self.history_index = self.history.len() - 1;
+    }
+
+    // The link that contains the target of an event, if it navigates within the page.
+    fn link(&self, event: usize) -> Option<String> {
+        let target = self.events[event].target;
+        let link = self
+            .ancestors(target)
+            .into_iter()
+            .find(|node| self.tag(*node) == Some("a") && self.attribute(*node, "href").is_some())?;
+        let href = self.attribute(link, "href")?;
+        let target = self.attribute(link, "target").unwrap_or_default();
+        // Other origins have a scheme, like `https:` or `mailto:`, or start with `//`
+        let external = href.starts_with("//")
+            || href
+                .split(['/', '?', '#'])
+                .next()
+                .is_some_and(|s| s.contains(':'));
+        if external
+            || self.attribute(link, "download").is_some()
+            || !(target.is_empty() || target == "_self")
+        {
+            return None;
+        }
+        Some(href.to_owned())
+    }
+}
+
+// The path, query and fragment of a URL without the origin.
+fn split_url(url: &str) -> (&str, &str, &str) {
+    let (rest, hash) = url.split_at(url.find('#').unwrap_or(url.len()));
+    let (path, search) = rest.split_at(rest.find('?').unwrap_or(rest.len()));
+    (path, search, hash)
+}
+
+// A compound selector such as `li.item#first[data-index="0"]`. Combinators are not supported.
+#[derive(Debug, Default)]
+struct Selector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl Selector {
+    fn parse(selector: &str) -> Result<Self, Error> {
+        let invalid = || {
+            error(
+                "SyntaxError",
+                format!("'{}' is not a valid selector", selector),
+            )
+        };
+        let is_name = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+        let name = |s: &str| -> usize { s.find(|c| !is_name(c)).unwrap_or(s.len()) };
+
+        let mut result = Self::default();
+        let mut rest = selector.trim();
+        if let Some(r) = rest.strip_prefix('*') {
+            rest = r;
+        } else {
+            let end = name(rest);
+            if end > 0 {
+                result.tag = Some(rest[..end].to_ascii_lowercase());
+            }
+            rest = &rest[end..];
+        }
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[1..];
+            match c {
+                '#' | '.' => {
+                    let end = name(rest);
+                    if end == 0 {
+                        return Err(invalid());
+                    }
+                    match c {
+                        '#' => result.id = Some(rest[..end].to_owned()),
+                        _ => result.classes.push(rest[..end].to_owned()),
+                    }
+                    rest = &rest[end..];
+                }
+                '[' => {
+                    let end = rest.find(']').ok_or_else(invalid)?;
+                    let attribute = match rest[..end].split_once('=') {
+                        Some((name, value)) => (
+                            name.trim().to_owned(),
+                            Some(value.trim().trim_matches(['"', '\'']).to_owned()),
+                        ),
+                        None => (rest[..end].trim().to_owned(), None),
+                    };
+                    result.attributes.push(attribute);
+                    rest = &rest[end + 1..];
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(result)
+    }
+
+    fn matches(&self, host: &Host, node: usize) -> bool {
+        let Some(tag) = host.tag(node) else {
+            return false;
+        };
+        let classes = host.classes(node);
+        self.tag.as_ref().is_none_or(|t| t == tag)
+            && self
+                .id
+                .as_ref()
+                .is_none_or(|id| host.attribute(node, "id") == Some(id))
+            && self.classes.iter().all(|c| classes.contains(c))
+            && self.attributes.iter().all(|(name, value)| {
+                match (host.attribute(node, name), value) {
+                    (Some(a), Some(v)) => a == v,
+                    (a, _) => a.is_some(),
+                }
+            })
+    }
+}
+
+// Property access, see `__get`, `__set`, `__call`, `__has` and `__keys` in `main.js`.
+impl Host {
+    fn get(&mut self, object: Object, key: &str) -> Result<Value, Error> {
+        let value = match object {
+            Object::Node(node) => self.get_node(node, key),
+            Object::Event(event) => {
+                let event = &self.events[event];
+                match key {
+                    "type" => Value::String(event.event_type.clone()),
+                    "target" => Value::Ref(Object::Node(event.target)),
+                    "currentTarget" => event
+                        .current_target
+                        .map_or(Value::Null, |node| Value::Ref(Object::Node(node))),
+                    "defaultPrevented" => Value::Bool(event.default_prevented),
+                    "bubbles" => Value::Bool(true),
+                    _ => lookup(&event.properties, key),
+                }
+            }
+            Object::Array(array) => match key {
+                "length" => Value::Number(self.arrays[array].len() as f64),
+                key => key
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| self.arrays[array].get(i).cloned())
+                    .unwrap_or(Value::Undefined),
+            },
+            Object::Plain(plain) => lookup(&self.plains[plain], key),
+            Object::Function(_) | Object::Promise(_) => Value::Undefined,
+        };
+        Ok(value)
+    }
+
+    fn get_node(&mut self, node: usize, key: &str) -> Value {
+        let ref_or_null =
+            |node: Option<usize>| node.map_or(Value::Null, |n| Value::Ref(Object::Node(n)));
+        let tag = self.tag(node).map(str::to_owned);
+        match key {
+            "nodeType" => match self.nodes[node].kind {
+                NodeKind::Element(_) => Value::Number(1.0),
+                NodeKind::Text(_) => Value::Number(3.0),
+                NodeKind::Comment(_) => Value::Number(8.0),
+                NodeKind::Document => Value::Number(9.0),
+                NodeKind::Window => Value::Undefined,
+            },
+            "nodeName" => match &self.nodes[node].kind {
+                NodeKind::Element(tag) => Value::String(tag.to_ascii_uppercase()),
+                NodeKind::Text(_) => Value::String("#text".into()),
+                NodeKind::Comment(_) => Value::String("#comment".into()),
+                NodeKind::Document => Value::String("#document".into()),
+                NodeKind::Window => Value::Undefined,
+            },
+            "tagName" => tag.map_or(Value::Undefined, |t| Value::String(t.to_ascii_uppercase())),
+            "nodeValue" | "data" => match &self.nodes[node].kind {
+                NodeKind::Text(text) | NodeKind::Comment(text) => Value::String(text.clone()),
+                _ if key == "data" => Value::Undefined,
+                _ => Value::Null,
+            },
+            "textContent" => Value::String(self.text_content(node)),
+            "innerHTML" => Value::String(self.html(node, false)),
+            "outerHTML" => Value::String(self.html(node, true)),
+            "id" | "className" if tag.is_some() => {
+                let name = if key == "id" { "id" } else { "class" };
+                Value::String(self.attribute(node, name).unwrap_or_default().to_owned())
+            }
+            "parentNode" => ref_or_null(self.nodes[node].parent),
+            "parentElement" => ref_or_null(self.nodes[node].parent),
+            "firstChild" => ref_or_null(self.nodes[node].children.first().copied()),
+            "lastChild" => ref_or_null(self.nodes[node].children.last().copied()),
+            "nextSibling" => self.sibling(node, 1),
+            "previousSibling" => self.sibling(node, -1),
+            "isConnected" => Value::Bool(self.ancestors(node).last() == Some(&HTML)),
+            "childNodes" | "children" => {
+                let children = self.nodes[node]
+                    .children
+                    .iter()
+                    .filter(|c| key == "childNodes" || self.is_element(**c))
+                    .map(|c| Value::Ref(Object::Node(*c)))
+                    .collect();
+                self.new_array(children)
+            }
+            key => match (
+                lookup(&self.nodes[node].properties, key),
+                tag.as_deref(),
+                key,
+            ) {
+                // Defaults of form controls
+                (Value::Undefined, Some("input" | "textarea" | "select"), "value") => {
+                    Value::String(String::new())
+                }
+                (Value::Undefined, Some("input"), "checked") => Value::Bool(false),
+                (value, _, _) => value,
+            },
+        }
+    }
+
+    fn set(&mut self, object: Object, key: &str, value: Value) -> Result<(), Error> {
+        match object {
+            Object::Node(node) => match (&mut self.nodes[node].kind, key) {
+                (
+                    NodeKind::Text(text) | NodeKind::Comment(text),
+                    "nodeValue" | "data" | "textContent",
+                ) => {
+                    *text = to_display(&value);
+                }
+                (NodeKind::Element(_), "textContent") => {
+                    self.clear(node);
+                    let text = to_display(&value);
+                    if !text.is_empty() {
+                        let child = self.create(NodeKind::Text(text));
+                        self.insert(node, child, None)?;
+                    }
+                }
+                (NodeKind::Element(_), "innerHTML") => {
+                    let html = to_display(&value);
+                    if !html.is_empty() {
+                        return Err(error(
+                            "NotSupportedError",
+                            "cannot parse HTML without a browser",
+                        ));
+                    }
+                    self.clear(node);
+                }
+                (NodeKind::Element(_), "id") => self.set_attribute(node, "id", to_display(&value)),
+                (NodeKind::Element(_), "className") => {
+                    self.set_attribute(node, "class", to_display(&value))
+                }
+                _ => insert(&mut self.nodes[node].properties, key, value),
+            },
+            Object::Event(event) => insert(&mut self.events[event].properties, key, value),
+            Object::Array(array) => {
+                let values = &mut self.arrays[array];
+                match key.parse::<usize>() {
+                    Ok(i) => {
+                        if values.len() <= i {
+                            values.resize(i + 1, Value::Undefined);
+                        }
+                        values[i] = value;
+                    }
+                    Err(_) if key == "length" => {
+                        let Value::Number(len) = value else {
+                            return Err(error("RangeError", "invalid array length"));
+                        };
+                        values.resize(len as usize, Value::Undefined);
+                    }
+                    Err(_) => {}
+                }
+            }
+            Object::Plain(plain) => insert(&mut self.plains[plain], key, value),
+            Object::Function(_) | Object::Promise(_) => {}
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, object: Object, method: &str, args: &[Value]) -> Result<Value, Error> {
+        let name = |value: &Value| to_display(value).to_ascii_lowercase();
+        let result = match (object, method) {
+            (Object::Node(node), "getAttribute") => self
+                .attribute(node, &name(arg(args, 0)))
+                .map_or(Value::Null, |v| Value::String(v.to_owned())),
+            (Object::Node(node), "hasAttribute") => {
+                Value::Bool(self.attribute(node, &name(arg(args, 0))).is_some())
+            }
+            (Object::Node(node), "setAttribute") => {
+                self.set_attribute(node, &name(arg(args, 0)), to_display(arg(args, 1)));
+                Value::Undefined
+            }
+            (Object::Node(node), "removeAttribute") => {
+                self.remove_attribute(node, &name(arg(args, 0)));
+                Value::Undefined
+            }
+            (Object::Node(node), "append") => {
+                for arg in args {
+                    let child = self.child_node(arg);
+                    self.insert(node, child, None)?;
+                }
+                Value::Undefined
+            }
+            (Object::Node(node), "appendChild") => {
+                let child = self::node(arg(args, 0))?;
+                self.insert(node, child, None)?;
+                Value::Ref(Object::Node(child))
+            }
+            (Object::Node(node), "insertBefore") => {
+                let child = self::node(arg(args, 0))?;
+                let before = match arg(args, 1) {
+                    Value::Null | Value::Undefined => None,
+                    before => Some(self::node(before)?),
+                };
+                self.insert(node, child, before)?;
+                Value::Ref(Object::Node(child))
+            }
+            (Object::Node(node), "remove") => {
+                self.detach(node);
+                Value::Undefined
+            }
+            (Object::Node(node), "after" | "replaceWith") => {
+                let Some(parent) = self.nodes[node].parent else {
+                    return Ok(Value::Undefined);
+                };
+                let next = match self.sibling(node, 1) {
+                    Value::Ref(Object::Node(next)) => Some(next),
+                    _ => None,
+                };
+                for arg in args {
+                    let child = self.child_node(arg);
+                    self.insert(parent, child, next)?;
+                }
+                if method == "replaceWith" {
+                    self.detach(node);
+                }
+                Value::Undefined
+            }
+            (Object::Node(node), "contains") => {
+                Value::Bool(self.ancestors(self::node(arg(args, 0))?).contains(&node))
+            }
+            (Object::Node(node), "querySelector") => {
+                self.query_selector(node, &to_display(arg(args, 0)), false)?
+            }
+            (Object::Node(_), "focus" | "blur") => Value::Undefined,
+            (Object::Event(event), "preventDefault") => {
+                self.events[event].default_prevented = true;
+                Value::Undefined
+            }
+            (Object::Event(event), "stopPropagation") => {
+                self.events[event].stopped = true;
+                Value::Undefined
+            }
+            (Object::Event(event), "stopImmediatePropagation") => {
+                self.events[event].stopped = true;
+                self.events[event].stopped_immediately = true;
+                Value::Undefined
+            }
+            (Object::Array(array), "push") => {
+                self.arrays[array].extend(args.iter().cloned());
+                Value::Number(self.arrays[array].len() as f64)
+            }
+            _ => {
+                return Err(error(
+                    "TypeError",
+                    format!("object.{} is not a function", method),
+                ))
+            }
+        };
+        Ok(result)
+    }
+
+    fn keys(&self, object: Object) -> Vec<String> {
+        let keys = |entries: &[(String, Value)]| entries.iter().map(|(k, _)| k.clone()).collect();
+        match object {
+            Object::Node(node) => keys(&self.nodes[node].properties),
+            Object::Event(event) => keys(&self.events[event].properties),
+            Object::Array(array) => (0..self.arrays[array].len())
+                .map(|i| i.to_string())
+                .collect(),
+            Object::Plain(plain) => keys(&self.plains[plain]),
+            Object::Function(_) | Object::Promise(_) => Vec::new(),
+        }
+    }
+
+    fn has(&mut self, object: Object, key: &str) -> Result<bool, Error> {
+        Ok(self.keys(object).iter().any(|k| k == key)
+            || (matches!(object, Object::Array(_)) && key == "length")
+            || self.get(object, key)? != Value::Undefined)
+    }
+
+    fn instance_of(&self, object: Object, class: &str) -> Result<bool, Error> {
+        let element = |tag: &str| -> &'static str {
+            match tag {
+                "a" => "HTMLAnchorElement",
+                "body" => "HTMLBodyElement",
+                "button" => "HTMLButtonElement",
+                "canvas" => "HTMLCanvasElement",
+                "div" => "HTMLDivElement",
+                "form" => "HTMLFormElement",
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => "HTMLHeadingElement",
+                "head" => "HTMLHeadElement",
+                "html" => "HTMLHtmlElement",
+                "img" => "HTMLImageElement",
+                "input" => "HTMLInputElement",
+                "label" => "HTMLLabelElement",
+                "li" => "HTMLLIElement",
+                "ol" => "HTMLOListElement",
+                "option" => "HTMLOptionElement",
+                "p" => "HTMLParagraphElement",
+                "select" => "HTMLSelectElement",
+                "span" => "HTMLSpanElement",
+                "table" => "HTMLTableElement",
+                "textarea" => "HTMLTextAreaElement",
+                "ul" => "HTMLUListElement",
+                _ => "HTMLUnknownElement",
+            }
+        };
+        let classes: Vec<&str> = match object {
+            Object::Node(node) => match &self.nodes[node].kind {
+                NodeKind::Element(tag) => vec!["Node", "Element", "HTMLElement", element(tag)],
+                NodeKind::Text(_) => vec!["Node", "CharacterData", "Text"],
+                NodeKind::Comment(_) => vec!["Node", "CharacterData", "Comment"],
+                NodeKind::Document => vec!["Node", "Document"],
+                NodeKind::Window => vec!["Window"],
+            },
+            Object::Event(_) => vec!["Event"],
+            Object::Array(_) => vec!["Array"],
+            Object::Plain(_) => vec![],
+            Object::Function(_) => vec!["Function"],
+            Object::Promise(_) => vec!["Promise"],
+        };
+        let known = class.starts_with("HTML") && class.ends_with("Element")
+            || [
+                "Object",
+                "Node",
+                "Element",
+                "CharacterData",
+                "Text",
+                "Comment",
+                "Document",
+                "Window",
+                "Event",
+                "Array",
+                "Function",
+                "Promise",
+            ]
+            .contains(&class);
+        if !known {
+            return Err(error("TypeError", format!("{} is not a class", class)));
+        }
+        Ok(class == "Object" || classes.contains(&class))
+    }
+}
+
+fn lookup(entries: &[(String, Value)], key: &str) -> Value {
+    entries
+        .iter()
+        .find(|(k, _)| k == key)
+        .map_or(Value::Undefined, |(_, v)| v.clone())
+}
+
+fn insert(entries: &mut Vec<(String, Value)>, key: &str, value: Value) {
+    match entries.iter_mut().find(|(k, _)| k == key) {
+        Some((_, v)) => *v = value,
+        None => entries.push((key.to_owned(), value)),
+    }
+}
+
+type SnippetFn = fn(&mut Host, &[Value]) -> Result<Value, Error>;
+
+// The snippets that can be invoked without a browser.
+const SNIPPETS: &[(&str, SnippetFn)] = &[
+    ("{}.append({})", |host, args| {
+        host.call(Object::Node(node(arg(args, 0))?), "append", &args[1..])
+    }),
+    ("{}.appendChild({})", |host, args| {
+        host.call(Object::Node(node(arg(args, 0))?), "appendChild", &args[1..])
+    }),
+    ("{}.insertBefore({},{})", |host, args| {
+        host.call(
+            Object::Node(node(arg(args, 0))?),
+            "insertBefore",
+            &args[1..],
+        )
+    }),
+    ("{}.after({})", |host, args| {
+        host.call(Object::Node(node(arg(args, 0))?), "after", &args[1..])
+    }),
+    ("{}.replaceWith({})", |host, args| {
+        host.call(Object::Node(node(arg(args, 0))?), "replaceWith", &args[1..])
+    }),
+    ("{}.remove()", |host, args| {
+        host.detach(node(arg(args, 0))?);
+        Ok(Value::Undefined)
+    }),
+    ("{}.setAttribute({},{})", |host, args| {
+        host.call(
+            Object::Node(node(arg(args, 0))?),
+            "setAttribute",
+            &args[1..],
+        )
+    }),
+    ("{}.classList.add(...{}.split(' '))", |host, args| {
+        let node = node(arg(args, 0))?;
+        for class in to_display(arg(args, 1)).split(' ') {
+            if class.is_empty() {
+                return Err(error("SyntaxError", "the token provided must not be empty"));
+            }
+            if !host.classes(node).iter().any(|c| c == class) {
+                host.toggle_class(node, class, true);
+            }
+        }
+        Ok(Value::Undefined)
+    }),
+    ("{}.classList.toggle({},{})", |host, args| {
+        let enabled = matches!(arg(args, 2), Value::Bool(true));
+        host.toggle_class(node(arg(args, 0))?, &to_display(arg(args, 1)), enabled);
+        Ok(Value::Bool(enabled))
+    }),
+    ("{}.style.setProperty({},{})", |host, args| {
+        let (property, value) = (to_display(arg(args, 1)), to_display(arg(args, 2)));
+        host.set_style(node(arg(args, 0))?, &property, &value);
+        Ok(Value::Undefined)
+    }),
+    ("{}.innerHTML = {}", |host, args| {
+        host.set(
+            Object::Node(node(arg(args, 0))?),
+            "innerHTML",
+            arg(args, 1).clone(),
+        )?;
+        Ok(Value::Undefined)
+    }),
+    ("{}.nodeValue = {}", |host, args| {
+        host.set(
+            Object::Node(node(arg(args, 0))?),
+            "nodeValue",
+            arg(args, 1).clone(),
+        )?;
+        Ok(Value::Undefined)
+    }),
+    ("{}[{}] = {}", |host, args| {
+        let Value::Ref(object) = arg(args, 0) else {
+            return Err(error("TypeError", "cannot set properties of a primitive"));
+        };
+        host.set(*object, &to_display(arg(args, 1)), arg(args, 2).clone())?;
+        Ok(Value::Undefined)
+    }),
+    ("return document.createTextNode('')", |host, _| {
+        Ok(Value::Ref(Object::Node(
+            host.create(NodeKind::Text(String::new())),
+        )))
+    }),
+    ("return document.createComment('')", |host, _| {
+        Ok(Value::Ref(Object::Node(
+            host.create(NodeKind::Comment(String::new())),
+        )))
+    }),
+    (hydration::INDEX_NODES, |host, args| {
+        let root = node(arg(args, 0))?;
+        let (mut elements, mut comments) = (Vec::new(), Vec::new());
+        let mut stack = host.nodes[root].children.clone();
+        stack.reverse();
+        while let Some(node) = stack.pop() {
+            let entry = match &host.nodes[node].kind {
+                NodeKind::Element(_) => host
+                    .attribute(node, "data-hk")
+                    .and_then(|key| key.parse::<usize>().ok())
+                    .map(|key| (&mut elements, key)),
+                NodeKind::Comment(text) => text
+                    .strip_prefix("hk:")
+                    .and_then(|key| key.parse::<usize>().ok())
+                    .map(|key| (&mut comments, key)),
+                _ => None,
+            };
+            if let Some((nodes, key)) = entry {
+                if nodes.len() <= key {
+                    nodes.resize(key + 1, Value::Undefined);
+                }
+                nodes[key] = Value::Ref(Object::Node(node));
+            }
+            stack.extend(host.nodes[node].children.iter().rev());
+        }
+        let elements = host.new_array(elements);
+        let comments = host.new_array(comments);
+        Ok(host.new_array(vec![elements, comments]))
+    }),
+    (hydration::STATIC_TEXT, |host, args| {
+        let children = &host.nodes[node(arg(args, 0))?].children;
+        let mut text = String::new();
+        for (i, child) in children.iter().enumerate() {
+            let dynamic = i > 0
+                && matches!(&host.nodes[children[i - 1]].kind,
+                    NodeKind::Comment(data) if data.starts_with("hk:"));
+            if let (NodeKind::Text(data), false) = (&host.nodes[*child].kind, dynamic) {
+                text.push_str(data);
+            }
+        }
+        Ok(Value::String(text))
+    }),
+    ("return []", |host, _| Ok(host.new_array(Vec::new()))),
+    ("return {}.length", |host, args| match arg(args, 0) {
+        Value::Ref(object) => host.get(*object, "length"),
+        Value::String(s) => Ok(Value::Number(s.encode_utf16().count() as f64)),
+        _ => Ok(Value::Undefined),
+    }),
+    ("return {}[{}]", |host, args| match arg(args, 0) {
+        Value::Ref(object) => host.get(*object, &to_display(arg(args, 1))),
+        _ => Ok(Value::Undefined),
+    }),
+    ("{}.push({})", |host, args| match arg(args, 0) {
+        Value::Ref(object) => host.call(*object, "push", &args[1..]),
+        _ => Err(error("TypeError", "object.push is not a function")),
+    }),
+    ("return window", |_, _| Ok(Value::Ref(Object::Node(WINDOW)))),
+    ("return document", |_, _| {
+        Ok(Value::Ref(Object::Node(DOCUMENT)))
+    }),
+    ("return location.pathname + location.search", |host, _| {
+        let (path, search, _) = split_url(&host.location);
+        Ok(Value::String(format!("{}{}", path, search)))
+    }),
+    ("return location.hash.slice(1)", |host, _| {
+        let (_, _, hash) = split_url(&host.location);
+        Ok(Value::String(hash.get(1..).unwrap_or_default().to_owned()))
+    }),
+    ("history.pushState(null, '', {})", |host, args| {
+        host.push_state(&to_display(arg(args, 0)));
+        Ok(Value::Undefined)
+    }),
+    ("history.pushState(null, '', '#' + {})", |host, args| {
+        host.push_state(&format!("#{}", to_display(arg(args, 0))));
+        Ok(Value::Undefined)
+    }),
+    // Re-implements the JS of the router, which is tested in a browser by `tests/mod.rs`
+    (router::LINK_CLICK, |host, args| {
+        let Value::Ref(Object::Event(event)) = *arg(args, 0) else {
+            return Err(error("TypeError", "expected an event"));
+        };
+        let properties = &host.events[event].properties;
+        let button = lookup(properties, "button");
+        let modified = ["metaKey", "ctrlKey", "shiftKey", "altKey"]
+            .iter()
+            .any(|key| lookup(properties, key) == Value::Bool(true));
+        if host.events[event].default_prevented
+            || !matches!(button, Value::Undefined | Value::Number(0.0))
+            || modified
+        {
+            return Ok(Value::Null);
+        }
+        match host.link(event) {
+            Some(href) if !href.starts_with('#') => {
+                host.events[event].default_prevented = true;
+                Ok(Value::String(host.resolve(&href)))
+            }
+            _ => Ok(Value::Null),
+        }
+    }),
+    ("console.log({})", |host, args| log(host, "log", args)),
+    ("console.info({})", |host, args| log(host, "info", args)),
+    ("console.warn({})", |host, args| log(host, "warn", args)),
+    ("console.error({})", |host, args| log(host, "error", args)),
+];
+
+fn log(host: &mut Host, level: &str, args: &[Value]) -> Result<Value, Error> {
+    let message = args.iter().map(to_display).collect::<Vec<_>>().join(" ");
+    host.console.push((level.to_owned(), message));
+    Ok(Value::Undefined)
+}
+
+// Finds a snippet by the id it was registered with, or by the hash of its code if it
+// was not registered (`csp` feature).
+fn snippet(id: u32) -> Result<SnippetFn, Error> {
+    let index = (id as usize).wrapping_sub(1);
+    let found = if cfg!(feature = "csp") {
+        SNIPPETS.iter().find(|(code, _)| js::snippet_id(code) == id)
+    } else {
+        SNIPPETS.get(index)
+    };
+    found.map(|(_, f)| *f).ok_or_else(|| {
+        let code = index
+            .checked_sub(SNIPPETS.len())
+            .and_then(|i| UNSUPPORTED.with_borrow(|codes| codes.get(i).cloned()));
+        let code = code.map_or(format!("snippet {}", id), |code| format!("`{}`", code));
+        error(
+            "NotSupportedError",
+            format!("cannot evaluate {} without a browser", code),
+        )
+    })
+}
+
+// Finds a snippet by its code, which is wrapped in a function by `js::__code`.
+fn find_snippet(wrapped: &str) -> Option<&'static (&'static str, SnippetFn)> {
+    SNIPPETS
+        .iter()
+        .find(|(code, _)| js::__code(code) == wrapped)
+}
+
+// Writes the result to the allocation buffer, like `respond` in `main.js`.
+fn respond(depth: u32, f: impl FnOnce(&mut Host) -> Result<Value, Error>) {
+    let buffer = HOST.with_borrow_mut(|host| {
+        host.collect_if_needed();
+        match f(host) {
+            Ok(value) => host.serialize(&[value], depth),
+            Err(error) => serialize_error(&error),
+        }
+    });
+    write_allocation(&buffer);
+}
+
+fn write_allocation(buffer: &[u8]) {
+    let ptr = js::get_allocation(buffer.len()) as *mut u8;
+    unsafe { std::ptr::copy_nonoverlapping(buffer.as_ptr(), ptr, buffer.len()) };
+}
+
+unsafe fn read(ptr: *const u8, len: u32) -> &'static [u8] {
+    if len == 0 {
+        return &[];
+    }
+    std::slice::from_raw_parts(ptr, len as usize)
+}
+
+unsafe fn read_str(ptr: *const u8, len: u32) -> String {
+    String::from_utf8_lossy(read(ptr, len)).into_owned()
+}
+
+// The imports of `main.js`.
+
+pub(crate) unsafe fn __invoke(c_ptr: *const u8, c_len: u32, p_ptr: *const u8, p_len: u32) {
+    let code = read_str(c_ptr, c_len);
+    let params = read(p_ptr, p_len);
+    respond(0, |host| {
+        let params = host.deserialize(params)?;
+        let (_, f) = find_snippet(&code).ok_or_else(|| {
+            error(
+                "NotSupportedError",
+                format!("cannot evaluate `{}` without a browser", code),
+            )
+        })?;
+        f(host, &params)
+    });
+}
+
+// The ids of snippets are stored in `static` snippets, which are shared by all threads, unlike
+// the mock DOM. So the id of a supported snippet is its index in `SNIPPETS` plus one on every
+// thread, and other snippets get the following ids on the thread that registers them.
+pub(crate) unsafe fn __register_snippet(c_ptr: *const u8, c_len: u32) -> u32 {
+    let code = read_str(c_ptr, c_len);
+    let index = match SNIPPETS.iter().position(|(c, _)| js::__code(c) == code) {
+        Some(index) => index,
+        None => UNSUPPORTED.with_borrow_mut(|codes| {
+            codes.push(code);
+            SNIPPETS.len() + codes.len() - 1
+        }),
+    };
+    index as u32 + 1
+}
+
+pub(crate) unsafe fn __invoke_snippet(id: u32, p_ptr: *const u8, p_len: u32) {
+    let params = read(p_ptr, p_len);
+    respond(0, |host| {
+        let f = snippet(id)?;
+        let params = host.deserialize(params)?;
+        f(host, &params)
+    });
+}
+
+pub(crate) unsafe fn __flush_commands(c_ptr: *const u8, c_len: u32, f_ptr: *const u32, f_len: u32) {
+    let buffer = read(c_ptr, c_len);
+    let freed = if f_len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(f_ptr, f_len as usize)
+    };
+    respond(0, |host| {
+        let mut run = || -> Result<Value, Error> {
+            let mut reader = Reader { buffer, i: 0 };
+            for _ in 0..reader.u32()? {
+                let id = reader.u32()?;
+                let len = reader.u32()? as usize;
+                let params = host.deserialize(reader.bytes(len)?)?;
+                snippet(id)?(host, &params)?;
+            }
+            Ok(Value::Undefined)
+        };
+        let result = run();
+        // Objects are freed after the calls that use them, also if a call fails
+        for id in freed {
+            host.free_object(*id);
+        }
+        result
+    });
+}
+
+// There are no animation frames, the queued calls are flushed by the next call that needs a response.
+pub(crate) unsafe fn __request_flush() {}
+
+pub(crate) unsafe fn __closure_return(r_ptr: *const u8, r_len: u32) {
+    let result = read(r_ptr, r_len);
+    HOST.with_borrow_mut(|host| {
+        host.closure_result = host
+            .deserialize(result)
+            .ok()
+            .and_then(|mut values| values.pop())
+            .unwrap_or(Value::Undefined);
+    });
+}
+
+pub(crate) unsafe fn __free_closure(_closure_id: u32) {}
+
+pub(crate) unsafe fn __free_object(id: u32) {
+    // Objects may still be dropped after the host, when the thread exits
+    let _ = HOST.try_with(|host| host.borrow_mut().free_object(id));
+}
+
+pub(crate) unsafe fn __create_element(t_ptr: *const u8, t_len: u32) {
+    let tag = read_str(t_ptr, t_len).to_ascii_lowercase();
+    respond(0, |host| {
+        Ok(Value::Ref(Object::Node(
+            host.create(NodeKind::Element(tag)),
+        )))
+    });
+}
+
+pub(crate) unsafe fn __query_selector(q_ptr: *const u8, q_len: u32) {
+    let query = read_str(q_ptr, q_len);
+    respond(0, |host| host.query_selector(HTML, &query, true));
+}
+
+pub(crate) unsafe fn __read_deep(object_id: u32) {
+    respond(u32::MAX, |host| {
+        let object = host.object(object_id)?;
+        // Same error as `serializeValue` in `main.js`
+        if host.is_cyclic(object, &mut Vec::new()) {
+            return Err(error("TypeError", "cannot copy a cyclic object"));
+        }
+        Ok(Value::Ref(object))
+    });
+}
+
+pub(crate) unsafe fn __read_array(object_id: u32) {
+    respond(1, |host| Ok(Value::Ref(host.object(object_id)?)));
+}
+
+pub(crate) unsafe fn __get(object_id: u32, k_ptr: *const u8, k_len: u32) {
+    let key = read_str(k_ptr, k_len);
+    respond(0, |host| {
+        let object = host.object(object_id)?;
+        host.get(object, &key)
+    });
+}
+
+pub(crate) unsafe fn __get_path(object_id: u32, p_ptr: *const u8, p_len: u32) {
+    let path = read_str(p_ptr, p_len);
+    respond(0, |host| {
+        let mut value = Value::Ref(host.object(object_id)?);
+        for key in path.split('.') {
+            value = match value {
+                Value::Ref(object) => host.get(object, key)?,
+                _ => Value::Undefined,
+            };
+        }
+        Ok(value)
+    });
+}
+
+pub(crate) unsafe fn __set(
+    object_id: u32,
+    k_ptr: *const u8,
+    k_len: u32,
+    v_ptr: *const u8,
+    v_len: u32,
+) {
+    let key = read_str(k_ptr, k_len);
+    let value = read(v_ptr, v_len);
+    respond(0, |host| {
+        let object = host.object(object_id)?;
+        let value = host.deserialize(value)?.pop().unwrap_or(Value::Undefined);
+        host.set(object, &key, value)?;
+        Ok(Value::Undefined)
+    });
+}
+
+pub(crate) unsafe fn __call(
+    object_id: u32,
+    m_ptr: *const u8,
+    m_len: u32,
+    a_ptr: *const u8,
+    a_len: u32,
+) {
+    let method = read_str(m_ptr, m_len);
+    let args = read(a_ptr, a_len);
+    respond(0, |host| {
+        let object = host.object(object_id)?;
+        let args = host.deserialize(args)?;
+        host.call(object, &method, &args)
+    });
+}
+
+pub(crate) unsafe fn __has(object_id: u32, k_ptr: *const u8, k_len: u32) {
+    let key = read_str(k_ptr, k_len);
+    respond(0, |host| {
+        let object = host.object(object_id)?;
+        Ok(Value::Bool(host.has(object, &key)?))
+    });
+}
+
+pub(crate) unsafe fn __keys(object_id: u32) {
+    respond(1, |host| {
+        let keys = host.keys(host.object(object_id)?);
+        Ok(host.new_array(keys.into_iter().map(Value::String).collect()))
+    });
+}
+
+pub(crate) unsafe fn __instance_of(object_id: u32, c_ptr: *const u8, c_len: u32) {
+    let class = read_str(c_ptr, c_len);
+    respond(0, |host| {
+        let object = host.object(object_id)?;
+        Ok(Value::Bool(host.instance_of(object, &class)?))
+    });
+}
+
+pub(crate) unsafe fn __add_event_listener(
+    object_id: u32,
+    e_ptr: *const u8,
+    e_len: u32,
+    callback_id: u32,
+    flags: u32,
+    _signal_id: u32,
+) {
+    let event = read_str(e_ptr, e_len);
+    HOST.with_borrow_mut(|host| {
+        let Ok(Object::Node(node)) = host.object(object_id) else {
+            return;
+        };
+        host.nodes[node].listeners.push(Listener {
+            event,
+            callback_id,
+            once: flags & 0x1 != 0,
+            capture: flags & 0x4 != 0,
+        });
+        host.listeners.insert(callback_id, node);
+    });
+}
+
+pub(crate) unsafe fn __remove_event_listener(callback_id: u32) {
+    let _ = HOST.try_with(|host| host.borrow_mut().remove_listener(callback_id));
+}
+
+pub(crate) unsafe fn __schedule_tasks() {
+    HOST.with_borrow_mut(|host| host.tasks_scheduled = true);
+}
+
+// Other objects resolve to themselves, like with `Promise.resolve`.
+pub(crate) unsafe fn __await_promise(object_id: u32, future_id: u32) {
+    HOST.with_borrow_mut(|host| match host.object(object_id) {
+        Ok(Object::Promise(promise)) => match &mut host.promises[promise] {
+            Promise::Pending(futures) => futures.push(future_id),
+            Promise::Settled(value, fulfilled) => {
+                let settled = (value.clone(), future_id, *fulfilled);
+                host.settled.push(settled);
+            }
+        },
+        object => {
+            let value = object.map_or(Value::Undefined, Value::Ref);
+            host.settled.push((value, future_id, true));
+        }
+    });
+}
+
+impl Host {
+    fn remove_listener(&mut self, callback_id: u32) {
+        if let Some(node) = self.listeners.remove(&callback_id) {
+            self.nodes[node]
+                .listeners
+                .retain(|l| l.callback_id != callback_id);
+        }
+    }
+}
+
+// Outer HTML of a node in the mock DOM, for example `<p class="intro">Hello</p>`.
+pub fn outer_html(object: &ObjectRef) -> String {
+    html(object, true)
+}
+
+// Inner HTML of a node in the mock DOM. Use `inner_html(&Elem::select("body")?.element)`
+// to inspect the whole page.
+#[cfg(any(test, feature = "testing"))]
+pub fn inner_html(object: &ObjectRef) -> String {
+    html(object, false)
+}
+
+fn html(object: &ObjectRef, outer: bool) -> String {
+    commands::flush();
+    HOST.with_borrow(|host| match host.object(object.id()) {
+        Ok(Object::Node(node)) => host.html(node, outer),
+        _ => String::new(),
+    })
+}
+
+// Dispatches an event on a node of the mock DOM, and returns `false` if a listener called
+// `preventDefault`. The event first runs the capturing listeners from the root down to the
+// target, and then bubbles up from the target to the root.
+#[cfg(any(test, feature = "testing"))]
+pub fn dispatch_event(target: &ObjectRef, event_type: &str) -> bool {
+    dispatch_event_with(target, event_type, &[])
+}
+
+// Same as `dispatch_event`, with additional properties of the event such as `("key", "Enter".into())`.
+#[cfg(any(test, feature = "testing"))]
+pub fn dispatch_event_with(target: &ObjectRef, event_type: &str, init: &[(&str, JsValue)]) -> bool {
+    commands::flush();
+
+    let (target, properties) = HOST.with_borrow_mut(|host| {
+        let Ok(Object::Node(target)) = host.object(target.id()) else {
+            panic!("events can only be dispatched on nodes");
+        };
+        let mut properties = Vec::new();
+        for (name, value) in init {
+            let value = js::serialize(std::slice::from_ref(value));
+            let value = host.deserialize(&value).ok().and_then(|mut v| v.pop());
+            properties.push((name.to_string(), value.unwrap_or(Value::Undefined)));
+        }
+        (target, properties)
+    });
+    let event = dispatch(target, event_type, properties);
+
+    let (prevented, link) = HOST.with_borrow(|host| {
+        let link = host.link(event).filter(|_| event_type == "click");
+        (host.events[event].default_prevented, link)
+    });
+    // Like in a browser, following a link to a fragment of the page changes the hash
+    if let (false, Some(href)) = (prevented, link) {
+        if href.starts_with('#') {
+            go_to(&href);
+        }
+    }
+    !prevented
+}
+
+// Dispatches an event, and returns its index. Events that reach the root of the page
+// also reach the document and the window.
+fn dispatch(target: usize, event_type: &str, properties: Vec<(String, Value)>) -> usize {
+    let (event, path) = HOST.with_borrow_mut(|host| {
+        host.dispatching += 1;
+        let event = host.new_event(Event {
+            event_type: event_type.to_owned(),
+            target,
+            current_target: None,
+            default_prevented: false,
+            stopped: false,
+            stopped_immediately: false,
+            properties,
+        });
+        let mut path = host.ancestors(target);
+        match path.last() {
+            Some(&HTML) => path.extend([DOCUMENT, WINDOW]),
+            Some(&DOCUMENT) => path.push(WINDOW),
+            _ => {}
+        }
+        (event, path)
+    });
+
+    let capture = path.iter().rev().map(|node| (*node, true));
+    let bubble = path.iter().map(|node| (*node, false));
+    for (node, capture) in capture.chain(bubble) {
+        let listeners: Vec<u32> = HOST.with_borrow(|host| {
+            host.nodes[node]
+                .listeners
+                .iter()
+                .filter(|l| l.event == event_type && l.capture == capture)
+                .map(|l| l.callback_id)
+                .collect()
+        });
+        for callback_id in listeners {
+            let event_id = HOST.with_borrow_mut(|host| {
+                // A listener may remove the listeners after it
+                if host.events[event].stopped_immediately
+                    || !host.listeners.contains_key(&callback_id)
+                {
+                    return None;
+                }
+                let once = host.nodes[node]
+                    .listeners
+                    .iter()
+                    .any(|l| l.callback_id == callback_id && l.once);
+                if once {
+                    host.remove_listener(callback_id);
+                }
+                host.events[event].current_target = Some(node);
+                Some(host.store(Object::Event(event)))
+            });
+            if let Some(event_id) = event_id {
+                callbacks::call_callback(callback_id, event_id);
+            }
+        }
+        if HOST.with_borrow(|host| host.events[event].stopped) {
+            break;
+        }
+    }
+
+    HOST.with_borrow_mut(|host| {
+        host.events[event].current_target = None;
+        host.dispatching -= 1;
+    });
+    event
+}
+
+// Navigates within the page, and dispatches `hashchange` on the window if the fragment changed.
+fn go_to(url: &str) {
+    let hash_changed = HOST.with_borrow_mut(|host| {
+        let previous = host.location.clone();
+        host.push_state(url);
+        split_url(&previous).2 != split_url(&host.location).2
+    });
+    if hash_changed {
+        dispatch(WINDOW, "hashchange", Vec::new());
+    }
+}
+
+// URL of the page without the origin, for example `/users/1?tab=2#top`. It is changed with
+// `history.pushState`, by following links to a fragment of the page, and by `history_go`.
+#[cfg(any(test, feature = "testing"))]
+pub fn location() -> String {
+    commands::flush();
+    HOST.with_borrow(|host| host.location.clone())
+}
+
+// Moves through the session history like `history.go`, for example `history_go(-1)` for the
+// back button. Dispatches `popstate` on the window, and `hashchange` if the fragment changed.
+#[cfg(any(test, feature = "testing"))]
+pub fn history_go(delta: isize) {
+    commands::flush();
+    let hash_changed = HOST.with_borrow_mut(|host| {
+        let index = host.history_index.checked_add_signed(delta)?;
+        let url = host.history.get(index)?.clone();
+        host.history_index = index;
+        let previous = std::mem::replace(&mut host.location, url);
+        Some(split_url(&previous).2 != split_url(&host.location).2)
+    });
+    let Some(hash_changed) = hash_changed else {
+        return;
+    };
+    dispatch(WINDOW, "popstate", Vec::new());
+    if hash_changed {
+        dispatch(WINDOW, "hashchange", Vec::new());
+    }
+}
+
+// Calls a closure in the same way as a JS function would, and returns its result.
+#[cfg(any(test, feature = "testing"))]
+pub fn call_closure(closure: &crate::closure::Closure, args: &[JsValue]) -> JsValue {
+    call(closure.id(), args)
+}
+
+// Calls a function of the mock DOM, that is a closure that was passed to JS, and returns its
+// result. Unlike `call_closure`, this does not need a handle that keeps the closure alive.
+#[cfg(any(test, feature = "testing"))]
+pub fn call_function(function: &ObjectRef, args: &[JsValue]) -> JsValue {
+    let id = HOST.with_borrow(|host| match host.object(function.id()) {
+        Ok(Object::Function(id)) => id,
+        _ => panic!("object is not a function"),
+    });
+    call(id, args)
+}
+
+#[cfg(any(test, feature = "testing"))]
+fn call(closure_id: u32, args: &[JsValue]) -> JsValue {
+    write_allocation(&js::serialize(args));
+    crate::closure::call_closure(closure_id);
+
+    let result = HOST.with_borrow_mut(|host| {
+        let result = std::mem::replace(&mut host.closure_result, Value::Undefined);
+        host.serialize(&[result], 0)
+    });
+    js::deserialize(&result)
+        .ok()
+        .and_then(|mut values| values.pop())
+        .unwrap_or(JsValue::Undefined)
+}
+
+// Runs the spawned tasks and settles the awaited promises, until there is nothing left to do.
+// This takes the place of the microtask queue of the browser.
+pub fn run_microtasks() {
+    loop {
+        // The values are kept in the host until they are passed to Rust, see `Host::collect`
+        let count = HOST.with_borrow(|host| host.settled.len());
+        for _ in 0..count {
+            let (buffer, future_id, fulfilled) = HOST.with_borrow_mut(|host| {
+                let (value, future_id, fulfilled) = host.settled.remove(0);
+                (host.serialize(&[value], 0), future_id, fulfilled)
+            });
+            write_allocation(&buffer);
+            runtime::resolve_future(future_id, fulfilled);
+        }
+        if !HOST.with_borrow_mut(|host| std::mem::take(&mut host.tasks_scheduled)) {
+            break;
+        }
+        runtime::run_tasks();
+    }
+}
+
+// Creates a pending promise, which is settled with `resolve_promise` or `reject_promise`.
+#[cfg(any(test, feature = "testing"))]
+pub fn create_promise() -> ObjectRef {
+    let id = HOST.with_borrow_mut(|host| {
+        host.promises.push(Promise::Pending(Vec::new()));
+        host.store(Object::Promise(host.promises.len() - 1))
+    });
+    ObjectRef::new(id)
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub fn resolve_promise(promise: &ObjectRef, value: &JsValue) {
+    settle_promise(promise, value, true);
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub fn reject_promise(promise: &ObjectRef, reason: &JsValue) {
+    settle_promise(promise, reason, false);
+}
+
+// The futures that await the promise are resolved in the next call to `run_microtasks`.
+fn settle_promise(promise: &ObjectRef, value: &JsValue, fulfilled: bool) {
+    let value = js::serialize(std::slice::from_ref(value));
+    HOST.with_borrow_mut(|host| {
+        let Ok(Object::Promise(promise)) = host.object(promise.id()) else {
+            panic!("object is not a promise");
+        };
+        let value = host
+            .deserialize(&value)
+            .ok()
+            .and_then(|mut values| values.pop())
+            .unwrap_or(Value::Undefined);
+        let Promise::Pending(futures) = &host.promises[promise] else {
+            // A promise can only be settled once
+            return;
+        };
+        for future_id in futures.clone() {
+            host.settled.push((value.clone(), future_id, fulfilled));
+        }
+        host.promises[promise] = Promise::Settled(value, fulfilled);
+    });
+}
+
+// Runs `f` with a new, empty document that is discarded afterwards, see `ssr::render_to_string`.
+// Objects that are created by `f` must not be used after it returns.
+pub(crate) fn isolated<R>(f: impl FnOnce() -> R) -> R {
+    commands::flush();
+    let previous = HOST.replace(Host::new());
+    // The router follows the location of the new document
+    let result = router::isolated(f);
+
+    // The listeners of the discarded document can never be called. They are freed while
+    // the new document is still installed, since their callbacks may own its objects.
+    let listeners: Vec<u32> = HOST.with_borrow(|host| host.listeners.keys().copied().collect());
+    for callback_id in listeners {
+        callbacks::free_callback(callback_id);
+    }
+    // Objects that were dropped while batching are freed in the new document
+    commands::flush();
+    HOST.replace(previous);
+    result
+}
+
+// Messages logged to the console since the last call, as `(level, message)`
+// where the level is `log`, `info`, `warn` or `error`.
+#[cfg(any(test, feature = "testing"))]
+pub fn take_console() -> Vec<(String, String)> {
+    HOST.with_borrow_mut(|host| std::mem::take(&mut host.console))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        element::Elem,
+        events::{self, InputEvent},
+        signals::Signal,
+    };
+    use std::{cell::Cell, rc::Rc};
+
+    #[test]
+    fn test_elem_tree() {
+        let list = Elem::new("ul").class("list dark").attr("data-count", "2");
+        list.clone()
+            .append(&Elem::new("li").text("a < b & c"))
+            .append(&Elem::new("li").attr("title", "\"quoted\"").text("second"));
+        let body = Elem::select("body").unwrap().append(&list);
+
+        assert_eq!(
+            outer_html(&list.element),
+            "<ul class=\"list dark\" data-count=\"2\"><li>a &lt; b &amp; c</li>\
+             <li title=\"&quot;quoted&quot;\">second</li></ul>"
+        );
+
+        // selectors only match nodes on the page
+        assert!(Elem::select("ul.dark[data-count=\"2\"]").is_ok());
+        assert!(Elem::select("ul.light").is_err());
+        list.clone().remove();
+        assert!(Elem::select("ul.dark").is_err());
+        assert_eq!(inner_html(&body.element), "");
+
+        // unknown code is not evaluated
+        let error = crate::js_fn!("return location.href")
+            .try_invoke(&[])
+            .unwrap_err();
+        assert_eq!(error.name, "NotSupportedError");
+    }
+
+    #[test]
+    fn test_signal_bindings() {
+        let count = Signal::new(0);
+        let button = Elem::new("button")
+            .class_toggle("even", move || count.get() % 2 == 0)
+            .style_dyn("width", move || format!("{}px", 10 * count.get()))
+            .text_dyn(count)
+            .on("click", move |_| count.update(|c| *c += 1));
+        assert_eq!(
+            outer_html(&button.element),
+            "<button class=\"even\" style=\"width: 0px;\">0</button>"
+        );
+
+        dispatch_event(&button.element, "click");
+        dispatch_event(&button.element, "click");
+        dispatch_event(&button.element, "click");
+        assert_eq!(count.get(), 3);
+        assert_eq!(
+            outer_html(&button.element),
+            "<button class=\"\" style=\"width: 30px;\">3</button>"
+        );
+    }
+
+    #[test]
+    fn test_event_dispatch() {
+        let logs: Rc<RefCell<Vec<String>>> = Default::default();
+        let log = |name: &'static str| {
+            let logs = logs.clone();
+            move |event: InputEvent| {
+                let value = event.target_value().unwrap_or_default();
+                logs.borrow_mut().push(format!("{}:{}", name, value));
+            }
+        };
+
+        // events bubble up from the target
+        let input = Elem::new("input").on_typed(events::INPUT, log("input"));
+        let form = Elem::new("form")
+            .on_typed(events::INPUT, log("form"))
+            .on_typed(events::SUBMIT, |event| event.prevent_default())
+            .append(&input);
+        input.element.set("value", "hello");
+        assert!(dispatch_event(&input.element, "input"));
+        assert_eq!(*logs.borrow(), vec!["input:hello", "form:hello"]);
+
+        // listeners can cancel the event
+        assert!(!dispatch_event(&form.element, "submit"));
+
+        // properties of the event
+        let keys = Rc::new(Cell::new(0));
+        let keys_clone = keys.clone();
+        let input = input.on_typed(events::KEYDOWN, move |event| {
+            if event.key() == "Enter" && !event.shift_key() {
+                keys_clone.set(keys_clone.get() + 1);
+            }
+        });
+        dispatch_event_with(&form.element, "keydown", &[("key", "Enter".into())]);
+        assert_eq!(keys.get(), 0);
+        dispatch_event_with(&input.element, "keydown", &[("key", "Enter".into())]);
+        assert_eq!(keys.get(), 1);
+    }
+
+    #[test]
+    fn test_isolated() {
+        let outer = Elem::new("p").text("outer");
+
+        // the objects owned by the listeners of the discarded document are freed in that document
+        isolated(|| {
+            let inner = Elem::new("p");
+            let object = inner.element.clone();
+            let inner = inner.on("click", move |_| drop(object.clone()));
+            Elem::select("body").unwrap().append(&inner);
+        });
+        assert_eq!(outer_html(&outer.element), "<p>outer</p>");
+    }
+
+    #[test]
+    fn test_collect() {
+        // the slots of nodes, events and arrays that can no longer be reached are reused
+        for _ in 0..10_000 {
+            let p = Elem::new("p").text("a");
+            dispatch_event(&p.element, "click");
+            p.element.get("childNodes");
+        }
+        HOST.with_borrow(|host| {
+            assert!(host.nodes.len() < 4 * COLLECT_MIN);
+            assert!(host.events.len() < 4 * COLLECT_MIN);
+            assert!(host.arrays.len() < 4 * COLLECT_MIN);
+        });
+
+        // but nodes on the page, or referenced by Rust, are kept with their children
+        let list = Elem::new("ul").append(&Elem::new("li").text("a"));
+        let body = Elem::select("body").unwrap();
+        body.clone().append(&Elem::new("p").text("b"));
+        HOST.with_borrow_mut(Host::collect);
+        assert_eq!(outer_html(&list.element), "<ul><li>a</li></ul>");
+        assert_eq!(inner_html(&body.element), "<p>b</p>");
+    }
+
+    #[test]
+    fn test_snippet_ids() {
+        // snippets are found by id with the `csp` feature, so their ids must be unique
+        let mut ids: Vec<u32> = SNIPPETS
+            .iter()
+            .map(|(code, _)| js::snippet_id(code))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), SNIPPETS.len());
+
+        // snippets registered on one thread have the same id on the others
+        let text = std::thread::spawn(|| outer_html(&Elem::new("p").text("a").element));
+        assert_eq!(text.join().unwrap(), "<p>a</p>");
+        assert_eq!(outer_html(&Elem::new("p").text("b").element), "<p>b</p>");
+    }
+}
@@ -0,0 +1,506 @@
+use std::{cell::RefCell, mem::ManuallyDrop, rc::Rc, str::FromStr};
+
+use crate::{
+    callbacks::add_event_listener,
+    console_error,
+    element::Elem,
+    js::ObjectRef,
+    js_fn,
+    signals::{unowned, untrack, Scope, Signal},
+};
+
+// Client-side routing. The current path is kept in a signal, which is updated by `navigate`,
+// by the back and forward buttons of the browser, and by clicks on links to the same origin.
+// In `Mode::Hash` the path is stored in the hash of the URL, as in `/#/users/1`, so that
+// the page also works on servers that cannot serve the same page for every path.
+// All routers of a page use the mode of the first one that is started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    History,
+    Hash,
+}
+
+// The state of the routers of a page.
+#[derive(Default)]
+struct State {
+    mode: Option<Mode>,
+    // The path signal is owned by its own scope, so that it can be disposed together with the
+    // state of another page. The scope is not dropped with the thread, since the signals may
+    // already be destroyed by then.
+    path: Option<(Signal<String>, ManuallyDrop<Scope>)>,
+    listening: bool,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = const {
+        RefCell::new(State {
+            mode: None,
+            path: None,
+            listening: false,
+        })
+    };
+}
+
+// Runs `f` with the state of another page, which is disposed afterwards, see `native::isolated`.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn isolated<R>(f: impl FnOnce() -> R) -> R {
+    let previous = STATE.replace(State::default());
+    let result = f();
+    let state = STATE.replace(previous);
+    if let Some((_, scope)) = state.path {
+        ManuallyDrop::into_inner(scope).dispose();
+    }
+    result
+}
+
+fn current_mode() -> Mode {
+    STATE.with_borrow(|state| state.mode.unwrap_or_default())
+}
+
+// The parameters of a matched route, for example `id` and `rest` of `/users/:id/*rest`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Parses a parameter, for example `params.get::<u32>("id")`.
+    pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get_str(name)?.parse().ok()
+    }
+}
+
+// Matches a path against a pattern. Segments of the pattern are either literal, `:name` which
+// matches a single segment, or `*name` which matches the remaining segments and must come last.
+// The query and the fragment of the path are ignored.
+pub fn match_path(pattern: &str, path: &str) -> Option<Params> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let mut segments = path.split('/').filter(|s| !s.is_empty());
+    let mut params = Params::default();
+    for part in pattern.split('/').filter(|s| !s.is_empty()) {
+        if let Some(name) = part.strip_prefix('*') {
+            let rest: Vec<String> = segments.by_ref().map(decode).collect();
+            params.values.push((name.to_owned(), rest.join("/")));
+            break;
+        }
+        let segment = segments.next()?;
+        match part.strip_prefix(':') {
+            Some(name) => params.values.push((name.to_owned(), decode(segment))),
+            None if part == segment => {}
+            None => return None,
+        }
+    }
+    match segments.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+// Decodes the percent-encoded bytes of a path segment.
+fn decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn read_path() -> String {
+    let path = match current_mode() {
+        Mode::History => js_fn!("return location.pathname + location.search").try_invoke(&[]),
+        Mode::Hash => js_fn!("return location.hash.slice(1)").try_invoke(&[]),
+    };
+    match path.map(|path| path.to_string()) {
+        Ok(Ok(path)) if !path.is_empty() => path,
+        _ => "/".to_owned(),
+    }
+}
+
+fn path_signal() -> Signal<String> {
+    if let Some(signal) = STATE.with_borrow(|state| state.path.as_ref().map(|p| p.0)) {
+        return signal;
+    }
+    let path = read_path();
+    let scope = unowned(Scope::detached);
+    let signal = scope.run(|| Signal::new(path));
+    STATE.with_borrow_mut(|state| state.path = Some((signal, ManuallyDrop::new(scope))));
+    signal
+}
+
+fn set_path(path: String) {
+    let signal = path_signal();
+    if signal.get_untracked() != path {
+        signal.set(path);
+    }
+}
+
+// The current path, read reactively.
+pub fn current_path() -> String {
+    path_signal().get()
+}
+
+// Navigates to a path without reloading the page, and adds it to the history.
+pub fn navigate(path: &str) {
+    match current_mode() {
+        Mode::History => js_fn!("history.pushState(null, '', {})").queue(&[path.into()]),
+        Mode::Hash => js_fn!("history.pushState(null, '', '#' + {})").queue(&[path.into()]),
+    }
+    set_path(path.to_owned());
+}
+
+fn listen(object: &ObjectRef, event: &str, callback: impl FnMut(ObjectRef) + 'static) {
+    // The router listens for the lifetime of the page
+    add_event_listener(object, event, callback).forget();
+}
+
+// The snippet that handles clicks on links, and returns the path to navigate to. Clicks on
+// links to the same origin are handled by the router, unless they open a new tab or window,
+// download a file, or only scroll to an anchor of the page.
+pub(crate) const LINK_CLICK: &str = "const e = {}; if (e.defaultPrevented || e.button !== 0 || e.metaKey || e.ctrlKey || e.shiftKey || e.altKey) return null; const a = e.target.closest && e.target.closest('a[href]'); if (!a || a.hasAttribute('download') || (a.target && a.target !== '_self') || a.origin !== location.origin || a.getAttribute('href').startsWith('#')) return null; e.preventDefault(); return a.pathname + a.search + a.hash";
+
+// Starts following the URL of the page, unless a router already does.
+fn start(mode: Mode) {
+    let conflict = STATE.with_borrow_mut(|state| match state.mode {
+        Some(current) => current != mode,
+        None => {
+            state.mode = Some(mode);
+            false
+        }
+    });
+    if conflict {
+        console_error!(
+            "router: cannot use {:?} mode, the page already uses {:?} mode",
+            mode,
+            current_mode()
+        );
+    }
+    set_path(read_path());
+    if STATE.with_borrow_mut(|state| std::mem::replace(&mut state.listening, true)) {
+        return;
+    }
+
+    // NOTE: without a browser, as when rendering on the server, the path stays "/"
+    if let Ok(Ok(window)) = js_fn!("return window").try_invoke(&[]).map(|w| w.to_ref()) {
+        listen(&window, "popstate", |_| set_path(read_path()));
+        listen(&window, "hashchange", |_| {
+            if current_mode() == Mode::Hash {
+                set_path(read_path());
+            }
+        });
+    }
+
+    if let Ok(Ok(document)) = js_fn!("return document")
+        .try_invoke(&[])
+        .map(|d| d.to_ref())
+    {
+        listen(&document, "click", |event| {
+            let path = js_fn!(LINK_CLICK).invoke(&[event.into()]);
+            if let Ok(path) = path.to_string() {
+                navigate(&path);
+            }
+        });
+    }
+}
+
+type Render = Rc<dyn Fn(&Params) -> Elem>;
+
+// Renders the component of the first route that matches the current path, for example
+//
+//     Router::new()
+//         .route("/", |_| home())
+//         .route("/users/:id", |params| user(params.get("id").unwrap()))
+//         .fallback(|_| not_found())
+//         .outlet()
+//
+#[derive(Default)]
+pub struct Router {
+    mode: Mode,
+    routes: Vec<(String, Render)>,
+    fallback: Option<Render>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn route(mut self, pattern: &str, render: impl Fn(&Params) -> Elem + 'static) -> Self {
+        self.routes.push((pattern.to_owned(), Rc::new(render)));
+        self
+    }
+
+    // Rendered when no route matches the current path.
+    pub fn fallback(mut self, render: impl Fn(&Params) -> Elem + 'static) -> Self {
+        self.fallback = Some(Rc::new(render));
+        self
+    }
+
+    // Starts the router, and returns the element in which the matched component is rendered.
+    // The component is rendered in its own scope, which is disposed when the route or its
+    // parameters change.
+    pub fn outlet(self) -> Elem {
+        start(self.mode);
+
+        let outlet = Elem::new("div");
+        // The index of the rendered route (`None` for the fallback), its parameters and its scope
+        let current: RefCell<Option<(Option<usize>, Params, Scope)>> = Default::default();
+        let element = outlet.element.clone();
+        // NOTE: the effect is owned by the outlet, so it only keeps a weak reference to it
+        let lifecycle = Rc::downgrade(&outlet.lifecycle);
+        outlet.lifecycle.bind(move || {
+            let Some(lifecycle) = lifecycle.upgrade() else {
+                return;
+            };
+            let elem = Elem {
+                element: element.clone(),
+                lifecycle,
+            };
+            let path = current_path();
+            let matched = self
+                .routes
+                .iter()
+                .enumerate()
+                .find_map(|(i, (pattern, _))| Some((Some(i), match_path(pattern, &path)?)))
+                .unwrap_or((None, Params::default()));
+            if let Some((index, params, _)) = &*current.borrow() {
+                if (index, params) == (&matched.0, &matched.1) {
+                    return;
+                }
+            }
+
+            // Dispose the previous component before rendering the next one
+            drop(current.take());
+            let (index, params) = matched;
+            let render = match index {
+                Some(i) => Some(&self.routes[i].1),
+                None => self.fallback.as_ref(),
+            };
+            let Some(render) = render else {
+                elem.children(&[]);
+                return;
+            };
+            let scope = Scope::detached();
+            let child = untrack(|| scope.run(|| render(&params)));
+            elem.children(&[&child]);
+            *current.borrow_mut() = Some((index, params, scope));
+        });
+        outlet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{js::JsValue, native, signals::on_cleanup, ssr};
+
+    // Renders a page, and logs its name when its scope is disposed.
+    fn page(name: String, disposed: &Rc<RefCell<Vec<String>>>) -> Elem {
+        let elem = Elem::new("p").text(&name);
+        let disposed = disposed.clone();
+        on_cleanup(move || disposed.borrow_mut().push(name));
+        elem
+    }
+
+    fn router(mode: Mode, disposed: &Rc<RefCell<Vec<String>>>) -> Elem {
+        let (home, user) = (disposed.clone(), disposed.clone());
+        Router::new()
+            .mode(mode)
+            .route("/", move |_| page("home".to_owned(), &home))
+            .route("/users/:id", move |params| {
+                page(format!("user {}", params.get_str("id").unwrap()), &user)
+            })
+            .fallback(|_| Elem::new("p").text("not found"))
+            .outlet()
+    }
+
+    // Clicks on a link with the given attributes, and returns whether the browser follows it.
+    fn click(attributes: &[(&str, &str)], init: &[(&str, JsValue)]) -> bool {
+        let label = Elem::new("span").text("link");
+        let link = attributes
+            .iter()
+            .fold(Elem::new("a"), |link, (name, value)| link.attr(name, value));
+        Elem::select("body").unwrap().append(&link.append(&label));
+        native::dispatch_event_with(&label.element, "click", init)
+    }
+
+    #[test]
+    fn test_match_path() {
+        // literal segments, trailing slashes and queries
+        assert_eq!(match_path("/", "/"), Some(Params::default()));
+        assert_eq!(
+            match_path("/about", "/about/?tab=1#top"),
+            Some(Params::default())
+        );
+        assert_eq!(match_path("/about", "/"), None);
+        assert_eq!(match_path("/about", "/about/me"), None);
+
+        // parameters are decoded and parsed
+        let params =
+            match_path("/users/:id/posts/:title", "/users/42/posts/hello%20world").unwrap();
+        assert_eq!(params.get::<u32>("id"), Some(42));
+        assert_eq!(params.get_str("title"), Some("hello world"));
+        assert_eq!(params.get::<u32>("title"), None);
+        assert_eq!(params.get_str("missing"), None);
+        assert_eq!(match_path("/users/:id", "/users"), None);
+
+        // wildcards match the remaining segments
+        let params = match_path("/users/:id/*rest", "/users/1/a/b").unwrap();
+        assert_eq!(params.get_str("rest"), Some("a/b"));
+        let params = match_path("/users/:id/*rest", "/users/1").unwrap();
+        assert_eq!(params.get_str("rest"), Some(""));
+        assert_eq!(
+            match_path("/*path", "/files/%E2%9C%93")
+                .unwrap()
+                .get_str("path"),
+            Some("files/✓")
+        );
+    }
+
+    #[test]
+    fn test_router() {
+        let disposed = Default::default();
+        let outlet = router(Mode::History, &disposed);
+        Elem::select("body").unwrap().append(&outlet);
+        assert_eq!(native::inner_html(&outlet.element), "<p>home</p>");
+
+        // navigating renders the matched route, and disposes the previous one
+        navigate("/users/1");
+        assert_eq!(native::location(), "/users/1");
+        assert_eq!(native::inner_html(&outlet.element), "<p>user 1</p>");
+        assert_eq!(*disposed.borrow(), vec!["home"]);
+        navigate("/users/2?tab=posts");
+        assert_eq!(native::inner_html(&outlet.element), "<p>user 2</p>");
+        navigate("/missing");
+        assert_eq!(native::inner_html(&outlet.element), "<p>not found</p>");
+        assert_eq!(*disposed.borrow(), vec!["home", "user 1", "user 2"]);
+
+        // the back and forward buttons dispatch `popstate`
+        native::history_go(-1);
+        assert_eq!(native::location(), "/users/2?tab=posts");
+        assert_eq!(native::inner_html(&outlet.element), "<p>user 2</p>");
+        native::history_go(-2);
+        assert_eq!(native::inner_html(&outlet.element), "<p>home</p>");
+        native::history_go(1);
+        assert_eq!(native::inner_html(&outlet.element), "<p>user 1</p>");
+
+        // removing the outlet disposes the route, and stops following the path
+        let element = outlet.element.clone();
+        outlet.remove();
+        assert_eq!(disposed.borrow().len(), 6);
+        assert_eq!(disposed.borrow().last().unwrap(), "user 1");
+        navigate("/users/3");
+        assert_eq!(native::inner_html(&element), "<p>user 1</p>");
+        assert_eq!(disposed.borrow().len(), 6);
+
+        // an outlet that is dropped without being added to the page is disposed
+        let disposed = Default::default();
+        drop(router(Mode::History, &disposed));
+        assert_eq!(*disposed.borrow(), vec!["user 3"]);
+    }
+
+    #[test]
+    fn test_router_links() {
+        let outlet = router(Mode::History, &Default::default());
+        Elem::select("body").unwrap().append(&outlet);
+
+        // clicks on links to the same origin are handled by the router
+        assert!(!click(&[("href", "/users/1")], &[]));
+        assert_eq!(native::location(), "/users/1");
+        assert_eq!(native::inner_html(&outlet.element), "<p>user 1</p>");
+        assert!(!click(&[("href", "2")], &[]));
+        assert_eq!(native::location(), "/users/2");
+
+        // unless they open a new tab, download a file or go to another origin
+        assert!(click(&[("href", "/")], &[("ctrlKey", true.into())]));
+        assert!(click(&[("href", "/")], &[("button", 1.into())]));
+        assert!(click(&[("href", "/"), ("download", "")], &[]));
+        assert!(click(&[("href", "/"), ("target", "_blank")], &[]));
+        assert!(click(&[("href", "https://example.com/")], &[]));
+        assert_eq!(native::location(), "/users/2");
+        assert_eq!(native::inner_html(&outlet.element), "<p>user 2</p>");
+    }
+
+    #[test]
+    fn test_router_hash() {
+        let disposed = Default::default();
+        let outlet = router(Mode::Hash, &disposed);
+        Elem::select("body").unwrap().append(&outlet);
+        assert_eq!(native::inner_html(&outlet.element), "<p>home</p>");
+
+        navigate("/users/1");
+        assert_eq!(native::location(), "/#/users/1");
+        assert_eq!(native::inner_html(&outlet.element), "<p>user 1</p>");
+
+        // links to a fragment change the hash, which is followed by the router
+        assert!(click(&[("href", "#/users/2")], &[]));
+        assert_eq!(native::location(), "/#/users/2");
+        assert_eq!(native::inner_html(&outlet.element), "<p>user 2</p>");
+
+        native::history_go(-2);
+        assert_eq!(native::location(), "/");
+        assert_eq!(native::inner_html(&outlet.element), "<p>home</p>");
+        assert_eq!(*disposed.borrow(), vec!["home", "user 1", "user 2"]);
+    }
+
+    #[test]
+    fn test_router_mode() {
+        let _history = router(Mode::History, &Default::default());
+
+        // a second router keeps the mode of the first one
+        let hash = router(Mode::Hash, &Default::default());
+        assert_eq!(
+            native::take_console(),
+            vec![(
+                "error".to_owned(),
+                "router: cannot use Hash mode, the page already uses History mode".to_owned()
+            )]
+        );
+        navigate("/users/1");
+        assert_eq!(native::location(), "/users/1");
+        assert_eq!(native::inner_html(&hash.element), "<p>user 1</p>");
+    }
+
+    #[test]
+    fn test_router_ssr() {
+        let outlet = router(Mode::History, &Default::default());
+
+        // rendering on the server does not change the path of the page
+        let html = ssr::render_to_string(|| {
+            navigate("/users/1");
+            router(Mode::History, &Default::default())
+        });
+        assert_eq!(html, "<div><p>user 1</p></div>");
+        assert_eq!(native::location(), "/");
+        assert_eq!(native::inner_html(&outlet.element), "<p>home</p>");
+        assert!(native::take_console().is_empty());
+    }
+}
@@ -1,117 +1,282 @@
-// use std::{
-//     cell::RefCell,
-//     future::Future,
-//     mem::ManuallyDrop,
-//     pin::Pin,
-//     rc::Rc,
-//     task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
-// };
-
-// use crate::callbacks::{create_async_callback, create_callback};
-// use crate::invoke::{Js, JsValue, ObjectRef};
-
-// pub enum FutureState<T> {
-//     Init,
-//     Pending(Waker),
-//     Ready(T),
-// }
-// pub struct FutureTask<T> {
-//     pub state: Rc<RefCell<FutureState<T>>>,
-// }
-
-// pub struct Runtime {}
-
-// type FutureRc<T> = Rc<RefCell<Pin<Box<dyn Future<Output = T>>>>>;
-
-// impl<T: Clone + 'static> Future for FutureTask<T> {
-//     type Output = T;
-
-//     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-//         let mut future_state = self.state.borrow_mut();
-//         match &*future_state {
-//             FutureState::Ready(result) => Poll::Ready(result.to_owned()),
-//             _ => {
-//                 *future_state = FutureState::Pending(cx.waker().to_owned());
-//                 Poll::Pending
-//             }
-//         }
-//     }
-// }
-
-// impl Runtime {
-//     fn poll<T: 'static>(future_rc: &FutureRc<T>) {
-//         let waker = Self::waker(&future_rc);
-//         let waker_forget = ManuallyDrop::new(waker);
-//         let context = &mut Context::from_waker(&waker_forget);
-//         let _poll = future_rc.borrow_mut().as_mut().poll(context);
-//     }
-
-//     // https://rust-lang.github.io/async-book/02_execution/03_wakeups.html
-//     fn waker<T: 'static>(future_rc: &FutureRc<T>) -> Waker {
-//         fn clone_fn<T: 'static>(ptr: *const ()) -> RawWaker {
-//             let future = unsafe { FutureRc::<T>::from_raw(ptr as *const _) };
-//             let _ = ManuallyDrop::new(future).clone();
-//             RawWaker::new(ptr, waker_vtable::<T>())
-//         }
-//         fn wake_fn<T: 'static>(ptr: *const ()) {
-//             let future = unsafe { FutureRc::<T>::from_raw(ptr as *const _) };
-//             let function_ref = create_callback(move |_| {
-//                 Runtime::poll(&future);
-//             });
-//             Js::invoke("window.setTimeout({},0)", &[function_ref.into()]);
-//         }
-//         fn drop_fn<T>(ptr: *const ()) {
-//             let future = unsafe { FutureRc::<T>::from_raw(ptr as *const _) };
-//             drop(future);
-//         }
-//         fn waker_vtable<T: 'static>() -> &'static RawWakerVTable {
-//             &RawWakerVTable::new(clone_fn::<T>, wake_fn::<T>, wake_fn::<T>, drop_fn::<T>)
-//         }
-//         let waker = RawWaker::new(&**future_rc as *const _ as *const (), waker_vtable::<T>());
-//         unsafe { Waker::from_raw(waker) }
-//     }
-
-//     pub fn block_on<T: 'static>(future: impl Future<Output = T> + 'static) {
-//         Self::poll(&Rc::new(RefCell::new(Box::pin(future))));
-//     }
-
-//     pub fn promise<F: FnOnce(ObjectRef) -> Vec<JsValue>>(
-//         code: &str,
-//         params_fn: F,
-//     ) -> FutureTask<ObjectRef> {
-//         let (callback_ref, future) = create_async_callback();
-//         Js::invoke(code, &params_fn(callback_ref));
-//         future
-//     }
-// }
-
-// #[cfg(test)]
-// mod tests {
-
-//     use super::*;
-
-//     #[test]
-//     fn test_await() {
-//         // create future
-//         let future = FutureTask {
-//             state: Rc::new(RefCell::new(FutureState::Init)),
-//         };
-//         let future_state = future.state.clone();
-//         assert_eq!(matches!(*future_state.borrow(), FutureState::Init), true);
-
-//         // set to ready
-//         *future_state.borrow_mut() = FutureState::Ready(true);
-//         assert_eq!(
-//             matches!(*future_state.borrow(), FutureState::Ready(true)),
-//             true
-//         );
-
-//         // block on future
-//         let has_run = Rc::new(RefCell::new(false));
-//         let has_run_clone = has_run.clone();
-//         Runtime::block_on(async move {
-//             *has_run_clone.borrow_mut() = future.await;
-//         });
-//         assert_eq!(*has_run.borrow(), true);
-//     }
-// }
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    future::Future,
+    mem::ManuallyDrop,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use crate::{
+    commands,
+    js::{self, JsValue, ObjectRef},
+};
+
+thread_local! {
+    // Tasks that have been woken up and are waiting to be polled.
+    static QUEUE: RefCell<VecDeque<Rc<Task>>> = const { RefCell::new(VecDeque::new()) };
+    // Whether JS has already been asked to call `run_tasks`.
+    static SCHEDULED: Cell<bool> = const { Cell::new(false) };
+    // States of the promises that are currently awaited, indexed by future id.
+    static FUTURES: RefCell<HashMap<u32, FutureStateRc>> = RefCell::new(HashMap::new());
+    static NEXT_FUTURE_ID: Cell<u32> = const { Cell::new(0) };
+}
+
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    fn __schedule_tasks();
+    fn __await_promise(object_id: u32, future_id: u32);
+}
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::{__await_promise, __schedule_tasks};
+
+type FutureStateRc = Rc<RefCell<FutureState<Result<JsValue, JsValue>>>>;
+
+pub enum FutureState<T> {
+    Init,
+    Pending(Waker),
+    Ready(T),
+}
+
+// A task owns a future that is driven to completion by the executor.
+// The future is removed once it has completed, so late wake-ups are ignored.
+struct Task {
+    future: RefCell<Option<Pin<Box<dyn Future<Output = ()>>>>>,
+    queued: Cell<bool>,
+}
+
+impl Task {
+    fn new(future: impl Future<Output = ()> + 'static) -> Rc<Self> {
+        Rc::new(Self {
+            future: RefCell::new(Some(Box::pin(future))),
+            queued: Cell::new(false),
+        })
+    }
+
+    fn poll(self: &Rc<Self>) {
+        self.queued.set(false);
+
+        let mut future = self.future.borrow_mut();
+        if let Some(f) = future.as_mut() {
+            let waker = Self::waker(self.clone());
+            let context = &mut Context::from_waker(&waker);
+            if f.as_mut().poll(context).is_ready() {
+                *future = None;
+            }
+        }
+    }
+
+    fn schedule(self: Rc<Self>) {
+        if self.queued.replace(true) {
+            return;
+        }
+        QUEUE.with_borrow_mut(|queue| queue.push_back(self));
+
+        // Ask JS to call `run_tasks` in a microtask
+        if !SCHEDULED.replace(true) {
+            unsafe { __schedule_tasks() };
+        }
+    }
+
+    // https://rust-lang.github.io/async-book/02_execution/03_wakeups.html
+    fn waker(task: Rc<Self>) -> Waker {
+        fn clone_fn(ptr: *const ()) -> RawWaker {
+            let task = ManuallyDrop::new(unsafe { Rc::from_raw(ptr as *const Task) });
+            let _ = ManuallyDrop::new(Rc::clone(&task));
+            RawWaker::new(ptr, &VTABLE)
+        }
+        fn wake_fn(ptr: *const ()) {
+            let task = unsafe { Rc::from_raw(ptr as *const Task) };
+            task.schedule();
+        }
+        fn wake_by_ref_fn(ptr: *const ()) {
+            let task = ManuallyDrop::new(unsafe { Rc::from_raw(ptr as *const Task) });
+            Rc::clone(&task).schedule();
+        }
+        fn drop_fn(ptr: *const ()) {
+            drop(unsafe { Rc::from_raw(ptr as *const Task) });
+        }
+        static VTABLE: RawWakerVTable =
+            RawWakerVTable::new(clone_fn, wake_fn, wake_by_ref_fn, drop_fn);
+
+        let raw_waker = RawWaker::new(Rc::into_raw(task) as *const (), &VTABLE);
+        unsafe { Waker::from_raw(raw_waker) }
+    }
+}
+
+#[no_mangle]
+pub fn run_tasks() {
+    // Only run the tasks that are queued right now. Tasks woken up while
+    // running are scheduled in a new microtask, so that JS gets a chance to run.
+    let tasks = QUEUE.with_borrow_mut(std::mem::take);
+    SCHEDULED.set(false);
+    for task in tasks {
+        task.poll();
+    }
+
+    commands::flush_or_log();
+}
+
+#[no_mangle]
+pub fn resolve_future(id: u32, success: bool) {
+    let result = match js::read_response() {
+        Ok(value) if success => Ok(value),
+        Ok(value) => Err(value),
+        Err(error) => Err(JsValue::String(error.to_string())),
+    };
+    let Some(state) = FUTURES.with_borrow_mut(|map| map.remove(&id)) else {
+        // The future was dropped before the promise settled
+        return;
+    };
+    let previous = std::mem::replace(&mut *state.borrow_mut(), FutureState::Ready(result));
+    if let FutureState::Pending(waker) = previous {
+        waker.wake();
+    }
+}
+
+// Spawns a future on the current thread. It is first polled in a microtask.
+pub fn spawn_local(future: impl Future<Output = ()> + 'static) {
+    Task::new(future).schedule();
+}
+
+// Polls the future right away, until it is pending for the first time.
+// The rest of the future is driven by its wakers, so this returns without blocking.
+pub fn block_on(future: impl Future<Output = ()> + 'static) {
+    Task::new(future).poll();
+}
+
+// A future that resolves when the underlying JS promise settles.
+// Resolves to `Ok` with the fulfilled value, or to `Err` with the rejection reason.
+pub struct JsFuture {
+    id: u32,
+    state: FutureStateRc,
+}
+
+impl JsFuture {
+    pub fn new(promise: &ObjectRef) -> Self {
+        let id = NEXT_FUTURE_ID.with(|next| next.replace(next.get().wrapping_add(1)));
+        let state = Rc::new(RefCell::new(FutureState::Init));
+        FUTURES.with_borrow_mut(|map| map.insert(id, state.clone()));
+
+        unsafe { __await_promise(promise.id(), id) };
+
+        Self { id, state }
+    }
+}
+
+impl From<ObjectRef> for JsFuture {
+    fn from(promise: ObjectRef) -> Self {
+        Self::new(&promise)
+    }
+}
+
+impl From<&ObjectRef> for JsFuture {
+    fn from(promise: &ObjectRef) -> Self {
+        Self::new(promise)
+    }
+}
+
+impl Future for JsFuture {
+    type Output = Result<JsValue, JsValue>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.borrow_mut();
+        match std::mem::replace(&mut *state, FutureState::Init) {
+            FutureState::Ready(result) => Poll::Ready(result),
+            _ => {
+                *state = FutureState::Pending(cx.waker().to_owned());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for JsFuture {
+    fn drop(&mut self) {
+        FUTURES.with_borrow_mut(|map| map.remove(&self.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::native;
+
+    struct TestFuture {
+        state: Rc<RefCell<FutureState<bool>>>,
+    }
+
+    impl Future for TestFuture {
+        type Output = bool;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let mut state = self.state.borrow_mut();
+            match &*state {
+                FutureState::Ready(result) => Poll::Ready(*result),
+                _ => {
+                    *state = FutureState::Pending(cx.waker().to_owned());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    fn await_promise(promise: &ObjectRef) -> Rc<RefCell<Option<Result<JsValue, JsValue>>>> {
+        let result: Rc<RefCell<Option<_>>> = Default::default();
+        let result_clone = result.clone();
+        let future = JsFuture::new(promise);
+        spawn_local(async move {
+            *result_clone.borrow_mut() = Some(future.await);
+        });
+        result
+    }
+
+    #[test]
+    fn test_pending_promise() {
+        // the future stays pending until the promise is fulfilled
+        let promise = native::create_promise();
+        let result = await_promise(&promise);
+        native::run_microtasks();
+        assert!(result.borrow().is_none());
+        native::resolve_promise(&promise, &42.into());
+        native::run_microtasks();
+        assert!(matches!(*result.borrow(), Some(Ok(JsValue::Number(n))) if n == 42.0));
+
+        // or rejected
+        let promise = native::create_promise();
+        let result = await_promise(&promise);
+        native::run_microtasks();
+        assert!(result.borrow().is_none());
+        native::reject_promise(&promise, &"failed".into());
+        native::run_microtasks();
+        assert!(matches!(&*result.borrow(), Some(Err(JsValue::String(s))) if s == "failed"));
+
+        // settled promises resolve right away
+        let result = await_promise(&promise);
+        native::run_microtasks();
+        assert!(matches!(&*result.borrow(), Some(Err(JsValue::String(s))) if s == "failed"));
+    }
+
+    #[test]
+    fn test_block_on() {
+        // create future
+        let future = TestFuture {
+            state: Rc::new(RefCell::new(FutureState::Init)),
+        };
+        let future_state = future.state.clone();
+        assert!(matches!(*future_state.borrow(), FutureState::Init));
+
+        // set to ready
+        *future_state.borrow_mut() = FutureState::Ready(true);
+
+        // block on future
+        let has_run = Rc::new(RefCell::new(false));
+        let has_run_clone = has_run.clone();
+        block_on(async move {
+            *has_run_clone.borrow_mut() = future.await;
+        });
+        assert!(*has_run.borrow());
+    }
+}
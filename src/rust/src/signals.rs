@@ -1,45 +1,634 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    any::{type_name, Any, TypeId},
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    marker::PhantomData,
+    rc::{Rc, Weak},
+};
 
-#[derive(Clone)]
+// The reactive graph is updated with a push-pull algorithm, see
+// https://github.com/milomg/reactively/blob/main/Reactive-algorithms.md
+//
+// When a signal is set, its observers are marked `Dirty` and everything downstream
+// is marked `Check`. Effects are then re-run, and pull the latest values of their sources:
+// a node that is `Check` first updates its sources, and only recomputes if one of them
+// has actually changed. This way every node is computed at most once per update,
+// and never observes a mix of old and new values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum State {
+    Clean,
+    Check,
+    Dirty,
+}
+
+// A node in the reactive graph. Signals are sources, effects are observers,
+// and memos are both.
+struct Node {
+    state: Cell<State>,
+    // Recomputes the node, and returns whether its value has changed
+    compute: RefCell<Option<Box<dyn FnMut() -> bool>>>,
+    effect: bool,
+    sources: RefCell<Vec<Rc<Node>>>,
+    observers: RefCell<Vec<Weak<Node>>>,
+}
+
+thread_local! {
+    // Node that is currently being computed, it subscribes to every node that it reads.
+    static OBSERVER: RefCell<Option<Rc<Node>>> = const { RefCell::new(None) };
+    // Effects that have to be re-run.
+    static EFFECTS: RefCell<VecDeque<Rc<Node>>> = const { RefCell::new(VecDeque::new()) };
+    static FLUSHING: Cell<bool> = const { Cell::new(false) };
+    // Number of nested `batch` calls that are currently running.
+    static BATCH_DEPTH: Cell<u32> = const { Cell::new(0) };
+    // Owner of the signals, memos and effects that are currently created.
+    static OWNER: RefCell<Option<Rc<Owner>>> = const { RefCell::new(None) };
+    // Effects created outside of any scope are kept alive for the lifetime of the page.
+    static ROOTS: RefCell<Vec<Rc<Node>>> = const { RefCell::new(Vec::new()) };
+    // States of all signals and memos, indexed by the key of their handle.
+    static ARENA: RefCell<Arena> = const { RefCell::new(Arena::new()) };
+    // Contexts provided outside of any scope.
+    static ROOT_CONTEXTS: RefCell<Option<HashMap<TypeId, Box<dyn Any>>>> = const { RefCell::new(None) };
+}
+
+impl Node {
+    fn new(state: State, effect: bool, compute: Option<Box<dyn FnMut() -> bool>>) -> Rc<Self> {
+        Rc::new(Self {
+            state: Cell::new(state),
+            compute: RefCell::new(compute),
+            effect,
+            sources: Default::default(),
+            observers: Default::default(),
+        })
+    }
+
+    fn observers(&self) -> Vec<Rc<Node>> {
+        let mut observers = self.observers.borrow_mut();
+        observers.retain(|o| o.strong_count() > 0);
+        observers.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    // Subscribes the current observer to this node.
+    fn track(self: &Rc<Self>) {
+        OBSERVER.with_borrow(|observer| {
+            let Some(observer) = observer else { return };
+            let mut sources = observer.sources.borrow_mut();
+            if !sources.iter().any(|s| Rc::ptr_eq(s, self)) {
+                sources.push(self.clone());
+                self.observers.borrow_mut().push(Rc::downgrade(observer));
+            }
+        });
+    }
+
+    fn mark(self: &Rc<Self>, state: State) {
+        if self.state.get() >= state {
+            return;
+        }
+        if self.effect && self.state.get() == State::Clean {
+            EFFECTS.with_borrow_mut(|effects| effects.push_back(self.clone()));
+        }
+        self.state.set(state);
+        for observer in self.observers() {
+            observer.mark(State::Check);
+        }
+    }
+
+    fn update_if_necessary(self: &Rc<Self>) {
+        if self.state.get() == State::Check {
+            let sources = self.sources.borrow().clone();
+            for source in sources {
+                source.update_if_necessary();
+                if self.state.get() == State::Dirty {
+                    break;
+                }
+            }
+        }
+        if self.state.get() == State::Dirty {
+            self.update();
+        }
+        self.state.set(State::Clean);
+    }
+
+    fn unsubscribe(self: &Rc<Self>) {
+        let this = Rc::downgrade(self);
+        for source in self.sources.take() {
+            source.observers.borrow_mut().retain(|o| !o.ptr_eq(&this));
+        }
+    }
+
+    fn update(self: &Rc<Self>) {
+        // Unsubscribe from the previous sources, they are tracked again while computing
+        self.unsubscribe();
+
+        let previous = OBSERVER.replace(Some(self.clone()));
+        let changed = match self.compute.borrow_mut().as_mut() {
+            Some(compute) => compute(),
+            None => false,
+        };
+        OBSERVER.set(previous);
+
+        self.state.set(State::Clean);
+        if changed {
+            for observer in self.observers() {
+                observer.state.set(State::Dirty);
+            }
+        }
+    }
+
+    fn dispose(self: &Rc<Self>) {
+        self.unsubscribe();
+
+        // NOTE: an effect can dispose itself while it is running, in which case
+        // its function is dropped together with the node
+        let compute = self
+            .compute
+            .try_borrow_mut()
+            .ok()
+            .and_then(|mut c| c.take());
+        drop(compute);
+    }
+}
+
+// Re-runs the pending effects, unless they are already being run or a batch is running.
+fn flush() {
+    if BATCH_DEPTH.get() > 0 || FLUSHING.replace(true) {
+        return;
+    }
+    while let Some(effect) = EFFECTS.with_borrow_mut(VecDeque::pop_front) {
+        effect.update_if_necessary();
+    }
+    FLUSHING.set(false);
+}
+
+// Runs a function, and defers re-running effects until it returns. Every effect
+// that depends on the signals set in the batch runs once, with the final values.
+pub fn batch<T>(f: impl FnOnce() -> T) -> T {
+    BATCH_DEPTH.set(BATCH_DEPTH.get() + 1);
+    let _guard = BatchGuard;
+    f()
+}
+
+// Ends a batch when dropped, also if its function panics.
+struct BatchGuard;
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        BATCH_DEPTH.set(BATCH_DEPTH.get() - 1);
+        // NOTE: while unwinding, the pending effects are left to the next flush,
+        // since a panic in one of them would abort
+        if !std::thread::panicking() {
+            flush();
+        }
+    }
+}
+
+// Runs a function without subscribing the current observer to the signals it reads.
+pub fn untrack<T>(f: impl FnOnce() -> T) -> T {
+    let previous = OBSERVER.take();
+    let value = f();
+    OBSERVER.set(previous);
+    value
+}
+
+// Runs a function outside of the current scope, so that the signals, memos and
+// effects it creates are kept alive for the lifetime of the page.
+pub(crate) fn unowned<T>(f: impl FnOnce() -> T) -> T {
+    let previous = OWNER.take();
+    let value = f();
+    OWNER.set(previous);
+    value
+}
+
+// Handles to signals and memos are keys into the arena. The generation of a slot is
+// incremented when its value is removed, so that stale handles cannot access a new value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key {
+    index: u32,
+    generation: u32,
+}
+
+struct Arena {
+    slots: Vec<(u32, Option<Rc<dyn Any>>)>,
+    free: Vec<u32>,
+}
+
+impl Arena {
+    const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, value: Rc<dyn Any>) -> Key {
+        match self.free.pop() {
+            Some(index) => {
+                let (generation, slot) = &mut self.slots[index as usize];
+                *slot = Some(value);
+                Key {
+                    index,
+                    generation: *generation,
+                }
+            }
+            None => {
+                self.slots.push((0, Some(value)));
+                Key {
+                    index: (self.slots.len() - 1) as u32,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    fn get(&self, key: Key) -> Option<Rc<dyn Any>> {
+        match self.slots.get(key.index as usize) {
+            Some((generation, slot)) if *generation == key.generation => slot.clone(),
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, key: Key) -> Option<Rc<dyn Any>> {
+        match self.slots.get_mut(key.index as usize) {
+            Some((generation, slot)) if *generation == key.generation => {
+                *generation = generation.wrapping_add(1);
+                self.free.push(key.index);
+                slot.take()
+            }
+            _ => None,
+        }
+    }
+}
+
+// Stores a state in the arena, owned by the current owner.
+fn insert_state(state: Rc<dyn Any>) -> Key {
+    let key = ARENA.with_borrow_mut(|arena| arena.insert(state));
+    OWNER.with_borrow(|owner| {
+        if let Some(owner) = owner {
+            owner.keys.borrow_mut().push(key);
+        }
+    });
+    key
+}
+
+fn get_state<S: 'static>(key: Key) -> Rc<S> {
+    ARENA
+        .with_borrow(|arena| arena.get(key))
+        .and_then(|state| state.downcast::<S>().ok())
+        .expect("signal has been disposed")
+}
+
+// An owner keeps signals, memos and effects alive until it is disposed.
+// Owners form a tree: disposing an owner also disposes its children.
+// Contexts are looked up through the parents, which also includes the
+// owners of detached scopes and effects.
+#[derive(Default)]
+struct Owner {
+    parent: Weak<Owner>,
+    keys: RefCell<Vec<Key>>,
+    effects: RefCell<Vec<Rc<Node>>>,
+    children: RefCell<Vec<Weak<Owner>>>,
+    cleanups: RefCell<Vec<Box<dyn FnOnce()>>>,
+    contexts: RefCell<HashMap<TypeId, Box<dyn Any>>>,
+}
+
+impl Owner {
+    // Creates an owner whose parent is the current owner, but that is not disposed with it.
+    fn new_detached() -> Rc<Self> {
+        let parent = OWNER.with_borrow(|parent| parent.as_ref().map(Rc::downgrade));
+        Rc::new(Self {
+            parent: parent.unwrap_or_default(),
+            keys: Default::default(),
+            effects: Default::default(),
+            children: Default::default(),
+            cleanups: Default::default(),
+            contexts: Default::default(),
+        })
+    }
+
+    // Creates an owner that is disposed together with the current owner.
+    fn new_child() -> Rc<Self> {
+        let owner = Self::new_detached();
+        if let Some(parent) = owner.parent.upgrade() {
+            let mut children = parent.children.borrow_mut();
+            children.retain(|c| c.strong_count() > 0);
+            children.push(Rc::downgrade(&owner));
+        }
+        owner
+    }
+
+    fn run<R>(self: &Rc<Self>, f: impl FnOnce() -> R) -> R {
+        let previous = OWNER.replace(Some(self.clone()));
+        let value = f();
+        OWNER.set(previous);
+        value
+    }
+
+    fn dispose(&self) {
+        for cleanup in self.cleanups.take().into_iter().rev() {
+            cleanup();
+        }
+        for child in self.children.take() {
+            if let Some(child) = child.upgrade() {
+                child.dispose();
+            }
+        }
+        for effect in self.effects.take() {
+            effect.dispose();
+        }
+
+        // NOTE: states are dropped outside of the arena borrow,
+        // since dropping a memo can dispose other owners
+        let keys = self.keys.take();
+        let states = ARENA.with_borrow_mut(|arena| {
+            keys.into_iter()
+                .filter_map(|key| arena.remove(key))
+                .collect::<Vec<_>>()
+        });
+        drop(states);
+        drop(self.contexts.take());
+    }
+}
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        self.dispose();
+    }
+}
+
+// A scope owns the signals, memos and effects that are created in `Scope::run`,
+// and disposes them when it is dropped. For example, a component can store a
+// scope and drop it when it is removed from the page. Scopes created while
+// another scope is running are disposed together with that scope.
+pub struct Scope {
+    owner: Rc<Owner>,
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Self {
+            owner: Owner::new_child(),
+        }
+    }
+
+    // Creates a scope that is only disposed when it is dropped itself,
+    // and not together with the current scope. It can still use the contexts
+    // that are provided to the current scope.
+    pub fn detached() -> Self {
+        Self {
+            owner: Owner::new_detached(),
+        }
+    }
+
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.owner.run(f)
+    }
+
+    pub fn dispose(self) {
+        drop(self);
+    }
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        self.owner.dispose();
+    }
+}
+
+// Registers a function that runs when the current scope is disposed,
+// or before the current effect re-runs.
+pub fn on_cleanup(f: impl FnOnce() + 'static) {
+    OWNER.with_borrow(|owner| {
+        if let Some(owner) = owner {
+            owner.cleanups.borrow_mut().push(Box::new(f));
+        }
+    });
+}
+
+// Provides a value to the current scope, and to the scopes and effects created in it.
+// Descendants retrieve the value by its type with `use_context`. Providing another
+// value of the same type replaces the previous one. Outside of any scope, the value is
+// provided to the whole page, but components in `html!` always run in their own scope.
+pub fn provide_context<T: 'static>(value: T) {
+    let value: Box<dyn Any> = Box::new(value);
+    let previous = OWNER.with_borrow(|owner| match owner {
+        Some(owner) => owner.contexts.borrow_mut().insert(TypeId::of::<T>(), value),
+        None => ROOT_CONTEXTS.with_borrow_mut(|contexts| {
+            contexts
+                .get_or_insert_with(HashMap::new)
+                .insert(TypeId::of::<T>(), value)
+        }),
+    });
+    // NOTE: the previous value is dropped outside of the borrows
+    drop(previous);
+}
+
+// Returns the value of type `T` that was provided by the closest scope,
+// or `None` if no scope provided it.
+pub fn use_context<T: Clone + 'static>() -> Option<T> {
+    let get = |contexts: &HashMap<TypeId, Box<dyn Any>>| {
+        contexts
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    };
+    let mut owner = OWNER.with_borrow(Clone::clone);
+    while let Some(current) = owner {
+        if let Some(value) = get(&current.contexts.borrow()) {
+            return Some(value);
+        }
+        owner = current.parent.upgrade();
+    }
+    ROOT_CONTEXTS.with_borrow(|contexts| contexts.as_ref().and_then(get))
+}
+
+// Same as `use_context`, but panics if no scope provided a value of type `T`.
+#[track_caller]
+pub fn expect_context<T: Clone + 'static>() -> T {
+    match use_context::<T>() {
+        Some(value) => value,
+        None => panic!(
+            "no context of type `{}` was provided, call `provide_context` in an ancestor scope",
+            type_name::<T>()
+        ),
+    }
+}
+
+struct SignalState<T> {
+    value: RefCell<T>,
+    node: Rc<Node>,
+}
+
+// Handle to a value that notifies its observers when it is set. The value is
+// owned by the scope in which the signal was created, handles can be copied freely.
 pub struct Signal<T> {
-    value: Rc<RefCell<T>>,
-    // NOTE: since `FnMut` can mutate state it has to go behind a smart pointer
-    subscribers: Rc<RefCell<Vec<Rc<RefCell<dyn FnMut() + 'static>>>>>,
+    key: Key,
+    ty: PhantomData<fn() -> T>,
 }
 
-impl<T: Clone + Send + 'static> Signal<T> {
-    pub fn new(value: T) -> &'static Self {
-        let signal = Self {
-            value: Rc::new(RefCell::new(value)),
-            subscribers: Default::default(),
+impl<T> Clone for Signal<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Signal<T> {}
+
+impl<T: 'static> Signal<T> {
+    pub fn new(value: T) -> Self {
+        let state = SignalState {
+            value: RefCell::new(value),
+            node: Node::new(State::Clean, false, None),
         };
-        &*Box::leak(Box::new(signal))
+        Self {
+            key: insert_state(Rc::new(state)),
+            ty: PhantomData,
+        }
     }
-    pub fn get(&self) -> T {
-        self.value.borrow().clone()
+    fn state(&self) -> Rc<SignalState<T>> {
+        get_state(self.key)
+    }
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let state = self.state();
+        state.node.track();
+        let value = state.value.borrow();
+        f(&value)
     }
     pub fn set(&self, new_value: T) {
+        self.update(|value| *value = new_value);
+    }
+    // Mutates the value in place, and notifies the observers.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        let state = self.state();
+
         // store value
-        *self.value.borrow_mut() = new_value;
+        f(&mut state.value.borrow_mut());
 
         // trigger effects
-        self.subscribers.borrow_mut().iter().for_each(|f| {
-            f.borrow_mut()();
-        });
+        for observer in state.node.observers() {
+            observer.mark(State::Dirty);
+        }
+        flush();
+    }
+}
+
+impl<T: Clone + 'static> Signal<T> {
+    pub fn get(&self) -> T {
+        self.with(T::clone)
+    }
+    pub fn get_untracked(&self) -> T {
+        self.state().value.borrow().clone()
     }
     pub fn on(&self, mut cb: impl FnMut(T) + 'static) {
-        // get callback
-        let signal_clone = self.clone();
-        let cb_ref = Rc::new(RefCell::new(move || {
-            cb(signal_clone.get());
-        }));
+        let signal = *self;
+        effect(move || {
+            let value = signal.get();
+            untrack(|| cb(value));
+        });
+    }
+}
+
+struct MemoState<T> {
+    value: Rc<RefCell<Option<T>>>,
+    node: Rc<Node>,
+}
+
+// A derived value that is recomputed when the signals it reads change.
+// Observers are only notified if the new value differs from the previous one.
+pub struct Memo<T> {
+    key: Key,
+    ty: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Memo<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
 
-        // store callback
-        self.subscribers.borrow_mut().push(cb_ref.to_owned());
+impl<T> Copy for Memo<T> {}
+
+impl<T: Clone + PartialEq + 'static> Memo<T> {
+    pub fn new(f: impl Fn() -> T + 'static) -> Self {
+        let value: Rc<RefCell<Option<T>>> = Default::default();
+        let value_clone = value.clone();
+        let compute = move || {
+            let new_value = f();
+            let changed = value_clone.borrow().as_ref() != Some(&new_value);
+            if changed {
+                *value_clone.borrow_mut() = Some(new_value);
+            }
+            changed
+        };
 
-        // trigger once
-        cb_ref.borrow_mut()();
+        // The value is computed lazily, when it is first read
+        let node = Node::new(State::Dirty, false, Some(Box::new(compute)));
+        Self {
+            key: insert_state(Rc::new(MemoState { value, node })),
+            ty: PhantomData,
+        }
+    }
+    fn state(&self) -> Rc<MemoState<T>> {
+        get_state(self.key)
+    }
+    pub fn get(&self) -> T {
+        self.state().node.track();
+        self.get_untracked()
     }
+    pub fn get_untracked(&self) -> T {
+        let state = self.state();
+        state.node.update_if_necessary();
+        let value = state.value.borrow().clone();
+        value.expect("memo has a value")
+    }
+}
+
+pub fn derived<T: Clone + PartialEq + 'static>(f: impl Fn() -> T + 'static) -> Memo<T> {
+    Memo::new(f)
+}
+
+// Values that can be read reactively: signals, memos and closures that read them.
+pub trait Reactive<T> {
+    fn get(&self) -> T;
+}
+
+impl<T: Clone + 'static> Reactive<T> for Signal<T> {
+    fn get(&self) -> T {
+        Signal::get(self)
+    }
+}
+
+impl<T: Clone + PartialEq + 'static> Reactive<T> for Memo<T> {
+    fn get(&self) -> T {
+        Memo::get(self)
+    }
+}
+
+impl<T, F: Fn() -> T> Reactive<T> for F {
+    fn get(&self) -> T {
+        self()
+    }
+}
+
+// Runs a function now, and again whenever the signals or memos it reads change.
+// Signals and effects created by the function are disposed before it re-runs.
+pub fn effect(mut f: impl FnMut() + 'static) {
+    let owner = Owner::new_detached();
+    let compute = move || {
+        owner.dispose();
+        owner.run(&mut f);
+        false
+    };
+    let node = Node::new(State::Dirty, true, Some(Box::new(compute)));
+    OWNER.with_borrow(|owner| match owner {
+        Some(owner) => owner.effects.borrow_mut().push(node.clone()),
+        None => ROOTS.with_borrow_mut(|roots| roots.push(node.clone())),
+    });
+    node.update_if_necessary();
 }
 
 #[cfg(test)]
@@ -70,4 +659,195 @@ mod tests {
         // check logs
         assert_eq!(*logs.borrow(), vec![10, 10, 20, 20, 30, 30]);
     }
+
+    #[test]
+    fn test_derived() {
+        // create diamond shaped graph
+        let logs: Rc<RefCell<Vec<u32>>> = Default::default();
+        let a = Signal::new(1);
+        let b = derived(move || a.get() * 2);
+        let c = derived(move || a.get() + 1);
+        let d = derived(move || b.get() + c.get());
+
+        // effect runs once per update, and never sees stale values
+        let logs_clone = logs.clone();
+        effect(move || logs_clone.borrow_mut().push(d.get()));
+        a.set(2);
+        a.set(3);
+        assert_eq!(*logs.borrow(), vec![4, 7, 10]);
+    }
+
+    #[test]
+    fn test_memo_equality() {
+        let runs = Rc::new(Cell::new(0));
+        let a = Signal::new(2);
+        let parity = derived(move || a.get() % 2);
+
+        // effect does not re-run if the memo value did not change
+        let runs_clone = runs.clone();
+        effect(move || {
+            parity.get();
+            runs_clone.set(runs_clone.get() + 1);
+        });
+        a.set(4);
+        a.set(6);
+        assert_eq!(runs.get(), 1);
+        a.set(7);
+        assert_eq!(runs.get(), 2);
+    }
+
+    #[test]
+    fn test_batch() {
+        let logs: Rc<RefCell<Vec<(u32, u32)>>> = Default::default();
+        let a = Signal::new(1);
+        let b = Signal::new(1);
+
+        // effect runs once after the batch, with the final values
+        let logs_clone = logs.clone();
+        effect(move || logs_clone.borrow_mut().push((a.get(), b.get())));
+        batch(|| {
+            a.set(2);
+            b.set(2);
+            batch(|| a.set(3));
+            assert_eq!(logs.borrow().len(), 1);
+        });
+        assert_eq!(*logs.borrow(), vec![(1, 1), (3, 2)]);
+
+        // the batch ends also if its function panics
+        let result = std::panic::catch_unwind(|| {
+            batch(|| {
+                a.set(4);
+                panic!("batch");
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(logs.borrow().len(), 2);
+        b.set(4);
+        assert_eq!(*logs.borrow(), vec![(1, 1), (3, 2), (4, 4)]);
+    }
+
+    #[test]
+    fn test_update() {
+        let logs: Rc<RefCell<Vec<usize>>> = Default::default();
+        let items = Signal::new(vec![1, 2]);
+
+        // mutate in place
+        let logs_clone = logs.clone();
+        effect(move || logs_clone.borrow_mut().push(items.with(Vec::len)));
+        items.update(|items| items.push(3));
+        assert_eq!(*logs.borrow(), vec![2, 3]);
+        assert_eq!(items.get(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_scope_dispose() {
+        let logs: Rc<RefCell<Vec<u32>>> = Default::default();
+        let a = Signal::new(1);
+
+        // create effect and signal in scope
+        let scope = Scope::new();
+        let logs_clone = logs.clone();
+        let b = scope.run(|| {
+            on_cleanup(move || logs_clone.borrow_mut().push(0));
+            let logs_clone = logs.clone();
+            a.on(move |v| logs_clone.borrow_mut().push(v));
+            Signal::new(2)
+        });
+        a.set(3);
+        assert_eq!(b.get(), 2);
+
+        // effect is unsubscribed after the scope is dropped
+        drop(scope);
+        a.set(4);
+        assert_eq!(*logs.borrow(), vec![1, 3, 0]);
+
+        // signal is disposed
+        let result = std::panic::catch_unwind(move || b.get());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_nested_effects() {
+        let runs = Rc::new(Cell::new(0));
+        let a = Signal::new(1);
+        let b = Signal::new(1);
+
+        // inner effects are disposed when the outer effect re-runs
+        let runs_clone = runs.clone();
+        effect(move || {
+            a.get();
+            let runs_clone = runs_clone.clone();
+            effect(move || {
+                b.get();
+                runs_clone.set(runs_clone.get() + 1);
+            });
+        });
+        a.set(2);
+        a.set(3);
+        assert_eq!(runs.get(), 3);
+        b.set(2);
+        assert_eq!(runs.get(), 4);
+    }
+
+    #[test]
+    fn test_context() {
+        #[derive(Debug, Clone, PartialEq)]
+        struct Theme(&'static str);
+
+        let logs: Rc<RefCell<Vec<Option<Theme>>>> = Default::default();
+        let a = Signal::new(1);
+        let scope = Scope::new();
+        let detached = scope.run(|| {
+            provide_context(Theme("dark"));
+            let inner = Scope::new();
+            inner.run(|| {
+                provide_context(Theme("light"));
+                assert_eq!(expect_context::<Theme>(), Theme("light"));
+            });
+
+            // descendants retrieve the closest value, also in effects and detached scopes
+            let logs_clone = logs.clone();
+            let detached = Scope::detached();
+            detached.run(|| {
+                effect(move || {
+                    a.get();
+                    logs_clone.borrow_mut().push(use_context::<Theme>());
+                })
+            });
+            detached
+        });
+        a.set(2);
+        assert_eq!(
+            *logs.borrow(),
+            vec![Some(Theme("dark")), Some(Theme("dark"))]
+        );
+
+        // contexts are not visible outside of the scope
+        assert_eq!(scope.run(use_context::<u32>), None);
+        drop(detached);
+        drop(scope);
+        let result = std::panic::catch_unwind(expect_context::<Theme>);
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.starts_with("no context of type `"));
+    }
+
+    #[test]
+    fn test_dynamic_dependencies() {
+        let logs: Rc<RefCell<Vec<u32>>> = Default::default();
+        let flag = Signal::new(true);
+        let a = Signal::new(1);
+        let b = Signal::new(2);
+
+        // effect only depends on the signals read in its last run
+        let logs_clone = logs.clone();
+        effect(move || {
+            let value = if flag.get() { a.get() } else { b.get() };
+            logs_clone.borrow_mut().push(value);
+        });
+        b.set(3);
+        flag.set(false);
+        a.set(4);
+        b.set(5);
+        assert_eq!(*logs.borrow(), vec![1, 3, 5]);
+    }
 }
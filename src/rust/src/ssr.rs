@@ -0,0 +1,78 @@
+use crate::{element::Elem, hydration, native, signals::Scope};
+
+// Server-side rendering. On a native target, `Elem` builds a tree in the mock DOM of
+// `native`, so the same component code can render a page to HTML, for example in an HTTP handler:
+// `render_to_string(|| Elem::new("h1").class("title").text("Hello"))`
+//
+// Each render uses a new document, which is discarded afterwards together with the scope of the
+// component. Tasks spawned by the component run before the HTML is generated, event listeners
+// are never called. Text and attribute values are escaped.
+pub fn render_to_string(component: impl FnOnce() -> Elem) -> String {
+    native::isolated(|| render(component))
+}
+
+// Same as `render_to_string`, with the markers that are needed to hydrate the
+// page in the browser with `hydration::hydrate`.
+pub fn render_to_string_hydratable(component: impl FnOnce() -> Elem) -> String {
+    native::isolated(|| hydration::render(|| render(component)))
+}
+
+fn render(component: impl FnOnce() -> Elem) -> String {
+    let scope = Scope::detached();
+    let elem = scope.run(component);
+    native::run_microtasks();
+    let html = native::outer_html(&elem.element);
+
+    drop(elem);
+    drop(scope);
+    html
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{runtime::spawn_local, signals::Signal};
+
+    fn page(title: &str) -> Elem {
+        let count = Signal::new(0);
+        let status = Elem::new("p");
+        let status_clone = status.clone();
+        spawn_local(async move {
+            status_clone.text("loaded");
+        });
+        Elem::new("main")
+            .attr("data-title", title)
+            .append(&Elem::new("h1").text(title))
+            .append(
+                &Elem::new("button")
+                    .text_dyn(count)
+                    .on("click", move |_| count.set(1)),
+            )
+            .append(&Elem::new("input").attr("value", "x"))
+            .append(&status)
+    }
+
+    #[test]
+    fn test_render_to_string() {
+        let body = Elem::select("body")
+            .unwrap()
+            .append(&Elem::new("div").text("app"));
+
+        let html = render_to_string(|| page("Tom & \"Jerry\" <3"));
+        assert_eq!(
+            html,
+            "<main data-title=\"Tom &amp; &quot;Jerry&quot; <3\"><h1>Tom &amp; \"Jerry\" &lt;3</h1>\
+             <button>0</button><input value=\"x\"><p>loaded</p></main>"
+        );
+        assert!(render_to_string(|| page("Home")).contains("<h1>Home</h1>"));
+
+        // markers are numbered in creation order, the status is created first
+        let html = render_to_string_hydratable(|| page("Home"));
+        assert!(html.starts_with("<main data-hk=\"1\" data-title=\"Home\"><h1 data-hk=\"2\">"));
+        assert!(html.ends_with("<p data-hk=\"0\">loaded</p></main>"));
+
+        // the page itself is not affected
+        assert_eq!(native::inner_html(&body.element), "<div>app</div>");
+    }
+}
@@ -0,0 +1,6 @@
+// Listeners of typed events receive the type of event of the event name.
+#[test]
+fn test_event_types() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/events/*.rs");
+}
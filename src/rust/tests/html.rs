@@ -0,0 +1,6 @@
+// Compile errors of `html!`, which point at the offending tag or attribute.
+#[test]
+fn test_html_errors() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
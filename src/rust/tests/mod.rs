@@ -11,7 +11,7 @@ use fantoccini::{ClientBuilder, Locator};
 pub const WASM_TRIPLET: &str = "wasm32-unknown-unknown";
 
 fn get_pid_on_port(port: u16) -> Option<u32> {
-    let output = Command::new("lsof").args(&["-ti", format!(":{port}").as_str()]).output().unwrap();
+    let output = Command::new("lsof").args(["-ti", format!(":{port}").as_str()]).output().unwrap();
     let stdout_opt = if output.stdout.is_empty() { None } else { Some(output.stdout) };
     stdout_opt.map(|o| std::str::from_utf8(&o).map(|p| p.trim().parse().unwrap()).unwrap())
 }
@@ -82,6 +82,14 @@ async fn test_wasm() -> Result<(), fantoccini::error::CmdError> {
     let body_str = body.html(true).await?;
     assert!(body_str.contains("hello"));
 
+    // links are followed by the router, with the JS of `router::LINK_CLICK`
+    assert!(body_str.contains("home page"));
+    client.find(Locator::Id("about-link")).await?.click().await?;
+    std::thread::sleep(Duration::from_millis(500));
+    let body_str = client.find(Locator::Css("body")).await?.html(true).await?;
+    assert!(body_str.contains("about page"));
+    assert_eq!(client.current_url().await?.fragment(), Some("/about"));
+
     // stop browser
     client.close().await?;
 
@@ -0,0 +1,27 @@
+use web::{components::Component, element::Elem, html};
+
+struct Badge {
+    elem: Elem,
+}
+
+struct BadgeProps {
+    label: &'static str,
+}
+
+impl Badge {
+    fn new(props: BadgeProps) -> Self {
+        Self {
+            elem: Elem::new("span").text(props.label),
+        }
+    }
+}
+
+impl Component for Badge {
+    fn to_elem(&self) -> &Elem {
+        &self.elem
+    }
+}
+
+fn main() {
+    let _ = html! { <div><Badge label="new" colour="red" /></div> };
+}
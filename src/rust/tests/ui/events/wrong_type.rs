@@ -0,0 +1,10 @@
+use web::{
+    element::Elem,
+    events::{KeyboardEvent, CLICK},
+};
+
+fn main() {
+    Elem::new("button").on_typed(CLICK, |event: KeyboardEvent| {
+        event.key();
+    });
+}
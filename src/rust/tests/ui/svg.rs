@@ -0,0 +1,9 @@
+use web::html;
+
+fn main() {
+    let _ = html! {
+        <div>
+            <svg viewBox="0 0 10 10"></svg>
+        </div>
+    };
+}
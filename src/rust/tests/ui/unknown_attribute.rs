@@ -0,0 +1,5 @@
+use web::html;
+
+fn main() {
+    let _ = html! { <a hreff="/">"home"</a> };
+}